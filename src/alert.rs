@@ -0,0 +1,255 @@
+//! Alert rules for a list of stashes and the sinks alerts are sent to
+
+use crate::{output::commission_percent, AccountId, BlockNumber, DumplingError, Result, Ss58Codec};
+use serde::Serialize;
+use sp_runtime::Perbill;
+use sp_staking::SessionIndex;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// Longest a webhook may take to connect, read the alert or answer, so an unresponsive one
+/// does not hold up the next alerts
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// State of the watched stashes at a block, see ApiFilling::stash_status
+#[derive(Clone, Debug)]
+pub struct Status {
+    pub block: BlockNumber,
+    pub session_index: SessionIndex,
+    pub stashes: Vec<StashStatus>,
+}
+
+#[derive(Clone, Debug)]
+pub struct StashStatus {
+    pub stash: AccountId,
+    pub in_session: bool,
+    /// Whether the stash is elected for the next era, `None` outside of the election window
+    pub queued: Option<bool>,
+    /// `None` when the stash is not a validator candidate
+    pub commission: Option<Perbill>,
+    pub nominators: usize,
+    /// Heartbeat received or block authored in the current session
+    pub online: bool,
+}
+
+/// Thresholds of the alert rules
+#[derive(Clone, Debug, Default)]
+pub struct Rules {
+    /// Alert when fewer nominators back a stash
+    pub min_nominators: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    DroppedFromSession,
+    MissingFromQueued,
+    FewNominators,
+    CommissionChanged,
+    ReportedOffline,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Alert {
+    pub block: BlockNumber,
+    pub stash: String,
+    pub kind: AlertKind,
+    pub message: String,
+}
+
+/// Alerts raised going from `prev` to `next`
+///
+/// Rules fire once, when their condition starts to hold.
+/// A stash is reported offline when its session ends without heartbeat nor authored block,
+/// as ImOnline does.
+pub fn evaluate(prev: &Status, next: &Status, rules: &Rules) -> Vec<Alert> {
+    let mut alerts = Vec::new();
+    for n in &next.stashes {
+        let p = match prev.stashes.iter().find(|p| p.stash == n.stash) {
+            Some(p) => p,
+            None => continue,
+        };
+        let mut alert = |kind: AlertKind, message: String| {
+            alerts.push(Alert {
+                block: next.block,
+                stash: n.stash.to_ss58check(),
+                kind,
+                message,
+            })
+        };
+        if p.in_session && !n.in_session {
+            alert(
+                AlertKind::DroppedFromSession,
+                format!("not a validator of session {}", next.session_index),
+            );
+        }
+        if n.queued == Some(false) && p.queued != Some(false) {
+            alert(
+                AlertKind::MissingFromQueued,
+                "not elected for the next era".to_string(),
+            );
+        }
+        if let Some(min) = rules.min_nominators {
+            if n.nominators < min && p.nominators >= min {
+                alert(
+                    AlertKind::FewNominators,
+                    format!("{} nominators, below {}", n.nominators, min),
+                );
+            }
+        }
+        if let (Some(from), Some(to)) = (p.commission, n.commission) {
+            if from != to {
+                alert(
+                    AlertKind::CommissionChanged,
+                    format!(
+                        "commission changed from {}% to {}%",
+                        commission_percent(from),
+                        commission_percent(to)
+                    ),
+                );
+            }
+        }
+        if next.session_index != prev.session_index && p.in_session && !p.online {
+            alert(
+                AlertKind::ReportedOffline,
+                format!(
+                    "no heartbeat nor authored block in session {}",
+                    prev.session_index
+                ),
+            );
+        }
+    }
+    alerts
+}
+
+/// Destination of alerts
+pub trait AlertSink {
+    fn send(&self, alert: &Alert) -> Result<()>;
+}
+
+/// Prints alerts to stdout, one line each
+pub struct LogSink;
+
+impl AlertSink for LogSink {
+    fn send(&self, alert: &Alert) -> Result<()> {
+        println!(
+            "{} ALERT #{} {} {:?}: {}",
+            chrono::Utc::now().to_rfc3339(),
+            alert.block,
+            alert.stash,
+            alert.kind,
+            alert.message
+        );
+        Ok(())
+    }
+}
+
+/// POSTs alerts as json to a plain http url, e.g. `http://127.0.0.1:8080/alerts`
+pub struct WebhookSink {
+    pub url: String,
+}
+
+impl AlertSink for WebhookSink {
+    fn send(&self, alert: &Alert) -> Result<()> {
+        let rest = if self.url.starts_with("http://") {
+            &self.url["http://".len()..]
+        } else {
+            return Err(DumplingError::Unsupported(format!(
+                "webhook {}, use an http:// url",
+                self.url
+            )));
+        };
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let address = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:80", host)
+        };
+        let body =
+            serde_json::to_string(alert).map_err(|e| DumplingError::Encode(e.to_string()))?;
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            path,
+            host,
+            body.len(),
+            body
+        );
+        let failed = |e: std::io::Error| DumplingError::Connection(format!("{}: {}", self.url, e));
+        let mut stream = connect(&address).map_err(failed)?;
+        stream
+            .set_read_timeout(Some(WEBHOOK_TIMEOUT))
+            .map_err(failed)?;
+        stream
+            .set_write_timeout(Some(WEBHOOK_TIMEOUT))
+            .map_err(failed)?;
+        stream.write_all(request.as_bytes()).map_err(failed)?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).map_err(failed)?;
+        match response.split_whitespace().nth(1) {
+            Some(status) if status.starts_with('2') => Ok(()),
+            status => Err(DumplingError::Connection(format!(
+                "{} answered {}",
+                self.url,
+                status.unwrap_or("nothing")
+            ))),
+        }
+    }
+}
+
+/// Connects to the first address of `address` answering within WEBHOOK_TIMEOUT
+fn connect(address: &str) -> std::io::Result<TcpStream> {
+    let mut last = std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("{} does not resolve", address),
+    );
+    for addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, WEBHOOK_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last = e,
+        }
+    }
+    Err(last)
+}
+
+/// Runs a shell command for every alert, with the alert as json on stdin
+///
+/// `DUMPLING_ALERT_KIND`, `DUMPLING_ALERT_STASH` and `DUMPLING_ALERT_MESSAGE`
+/// are set for simple scripts.
+pub struct CommandSink {
+    pub command: String,
+}
+
+impl AlertSink for CommandSink {
+    fn send(&self, alert: &Alert) -> Result<()> {
+        let body =
+            serde_json::to_string(alert).map_err(|e| DumplingError::Encode(e.to_string()))?;
+        let kind = serde_json::to_value(alert.kind)
+            .ok()
+            .and_then(|k| k.as_str().map(String::from))
+            .unwrap_or_default();
+        let failed = |e: std::io::Error| DumplingError::Io(format!("{}: {}", self.command, e));
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .env("DUMPLING_ALERT_KIND", kind)
+            .env("DUMPLING_ALERT_STASH", &alert.stash)
+            .env("DUMPLING_ALERT_MESSAGE", &alert.message)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(failed)?;
+        if let Some(stdin) = child.stdin.as_mut() {
+            stdin.write_all(body.as_bytes()).map_err(failed)?;
+        }
+        let status = child.wait().map_err(failed)?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(DumplingError::Io(format!("{}: {}", self.command, status)))
+        }
+    }
+}
@@ -0,0 +1,629 @@
+//! Async counterpart of ApiFilling over an AsyncStorageSource, enabled with the `async` feature
+
+use crate::{
+    alert,
+    async_source::{AsyncStorageSource, WsSource},
+    balance::TokenInfo,
+    diff, metrics,
+    query::{
+        self, ledger_keys, nominator_list, nominator_map, nominators_by_validator, staking_lock,
+        waitlist, NominatorList, Staker,
+    },
+    set_default_ss58_version, storage, AccountId, AccountInfo, ActiveEraInfo, Balance, BlakeTwo256,
+    BlockNumber, Chain, DumplingError, ElectionResult, EraRewardPoints, Hash, Header, MemorySource,
+    Nominator, Result, Ss58Codec, StorageKey, UnclaimedPayout, WaitingValidator,
+    DEFAULT_BATCH_SIZE, DEFAULT_HISTORY_DEPTH, DEFAULT_PAGE_SIZE, SNAPSHOT_VALUES,
+};
+use balances::BalanceLock;
+use codec::Decode;
+use futures::future::try_join_all;
+use serde_json::{json, Value};
+use sp_staking::SessionIndex;
+use staking::{EraIndex, Exposure, Nominations, StakingLedger, ValidatorPrefs};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+/// AsyncApiFilling exposes the queries of ApiFilling as futures
+///
+/// Batched storage reads are sent concurrently instead of one after the other.
+/// Signing and submitting extrinsics stay on ApiFilling.
+pub struct AsyncApiFilling<S: AsyncStorageSource = WsSource> {
+    pub source: S,
+    pub chain: Chain,
+    /// Token of the chain, from the node's `system_properties` when available
+    pub token: TokenInfo,
+    /// Number of storage keys fetched per `state_queryStorageAt` request
+    pub batch_size: usize,
+    /// Number of keys per `state_getKeysPaged` page
+    pub page_size: u32,
+}
+
+impl AsyncApiFilling<WsSource> {
+    /// Create AsyncApiFilling, see ApiFilling::new for the arguments
+    pub async fn new(url: &str, chain: &str) -> Result<Self> {
+        let chain = Chain::from_str(chain)?;
+        let source = WsSource::new(url).await?;
+        Ok(Self::with_source(source, chain).await)
+    }
+}
+
+impl<S: AsyncStorageSource> AsyncApiFilling<S> {
+    /// Create AsyncApiFilling reading from any source, e.g. a MemorySource in tests
+    pub async fn with_source(source: S, chain: Chain) -> Self {
+        set_default_ss58_version(chain.ss58_format());
+        let token = source
+            .rpc("system_properties", json!([]))
+            .await
+            .ok()
+            .and_then(|p| TokenInfo::from_properties(&p))
+            .unwrap_or_else(|| TokenInfo::for_chain(chain));
+        Self {
+            source,
+            chain,
+            token,
+            batch_size: DEFAULT_BATCH_SIZE,
+            page_size: DEFAULT_PAGE_SIZE,
+        }
+    }
+
+    /// Raw json-rpc call returning the `result` field, `Value::Null` if the node has none
+    pub async fn rpc(&self, method: &str, params: Value) -> Result<Value> {
+        self.source.rpc(method, params).await
+    }
+
+    pub async fn finalized_head(&self) -> Result<(Hash, Header<BlockNumber, BlakeTwo256>)> {
+        let hash = self.source.finalized_head().await?;
+        Ok((hash, self.header(hash).await?))
+    }
+
+    pub async fn header(&self, hash: Hash) -> Result<Header<BlockNumber, BlakeTwo256>> {
+        self.source.header(hash).await
+    }
+
+    /// Hash of the block at `number` on the canonical chain
+    pub async fn block_hash(&self, number: BlockNumber) -> Result<Hash> {
+        self.source.block_hash(Some(number)).await
+    }
+
+    /// Resolves a `0x` prefixed block hash or a block number
+    pub async fn resolve_block(&self, at: &str) -> Result<Hash> {
+        if at.starts_with("0x") {
+            storage::parse_hash(at)
+        } else {
+            let number = at
+                .parse::<BlockNumber>()
+                .map_err(|_| DumplingError::Decode(format!("block {}", at)))?;
+            self.block_hash(number).await
+        }
+    }
+
+    pub async fn active_era(&self, block_hash: Option<Hash>) -> Result<ActiveEraInfo> {
+        self.storage_value("Staking", "ActiveEra", block_hash).await
+    }
+
+    pub async fn planned_era(&self, block_hash: Option<Hash>) -> Result<EraIndex> {
+        self.storage_value("Staking", "CurrentEra", block_hash)
+            .await
+    }
+
+    pub async fn session_index(&self, block_hash: Option<Hash>) -> Result<SessionIndex> {
+        self.storage_value("Session", "CurrentIndex", block_hash)
+            .await
+    }
+
+    pub async fn waiting_validators(
+        &self,
+        block_hash: Option<Hash>,
+    ) -> Result<HashMap<String, WaitingValidator>> {
+        let block_hash = Some(self.pin(block_hash).await?);
+        let v_to_n = self.validators_to_nominators(block_hash).await?;
+        self.waiting_backed_by(&v_to_n, block_hash).await
+    }
+
+    /// Waiting validators with their nominators from `v_to_n`
+    async fn waiting_backed_by(
+        &self,
+        v_to_n: &HashMap<AccountId, Vec<String>>,
+        block_hash: Option<Hash>,
+    ) -> Result<HashMap<String, WaitingValidator>> {
+        let storage_keys = self.map_keys("Staking", "Validators", block_hash).await?;
+        let stashes = storage_keys
+            .iter()
+            .map(storage::account_from_key)
+            .collect::<Result<Vec<AccountId>>>()?;
+
+        let (prefs, staked, ledgers) = futures::try_join!(
+            self.decode_batch::<ValidatorPrefs>(&storage_keys, block_hash),
+            self.staked(&stashes, block_hash),
+            self.ledgers(&stashes, block_hash),
+        )?;
+
+        waitlist(stashes, prefs, staked, ledgers, v_to_n)
+    }
+
+    pub async fn validators_to_nominators(
+        &self,
+        block_hash: Option<Hash>,
+    ) -> Result<HashMap<AccountId, Vec<String>>> {
+        let nom_list = self.get_nominators(block_hash).await?;
+        Ok(nominators_by_validator(nom_list))
+    }
+
+    pub async fn session_validators(&self, block_hash: Option<Hash>) -> Result<Vec<AccountId>> {
+        self.storage_value("Session", "Validators", block_hash)
+            .await
+    }
+
+    /// Election result queued for the next era, `None` outside of the election window
+    pub async fn queued_validators(
+        &self,
+        block_hash: Option<Hash>,
+    ) -> Result<Option<ElectionResult<AccountId, Balance>>> {
+        self.decode_value(&storage::value_key("Staking", "QueuedElected"), block_hash)
+            .await
+    }
+
+    /// Nonce and balances of the account, `None` when it does not exist
+    pub async fn account_info(
+        &self,
+        account: &AccountId,
+        block_hash: Option<Hash>,
+    ) -> Result<Option<AccountInfo>> {
+        self.decode_value(&storage::account_key(account), block_hash)
+            .await
+    }
+
+    pub async fn nominators(
+        &self,
+        block_hash: Option<Hash>,
+    ) -> Result<HashMap<String, Option<Nominator>>> {
+        let block_hash = Some(self.pin(block_hash).await?);
+        let nom_list = self.get_nominators(block_hash).await?;
+        self.nominators_staked(nom_list, block_hash).await
+    }
+
+    /// Nominators of `nom_list` with their staked balances
+    async fn nominators_staked(
+        &self,
+        nom_list: NominatorList,
+        block_hash: Option<Hash>,
+    ) -> Result<HashMap<String, Option<Nominator>>> {
+        let accounts: Vec<AccountId> = nom_list.iter().map(|n| n.1.clone()).collect();
+        let staked = self.staked(&accounts, block_hash).await?;
+
+        Ok(nominator_map(nom_list, staked))
+    }
+
+    /// Total and per-validator reward points of `era`, none until the era starts
+    pub async fn era_reward_points(
+        &self,
+        era: EraIndex,
+        block_hash: Option<Hash>,
+    ) -> Result<EraRewardPoints<AccountId>> {
+        Ok(self
+            .decode_value(&storage::reward_points_key(era), block_hash)
+            .await?
+            .unwrap_or_else(|| EraRewardPoints {
+                total: 0,
+                individual: BTreeMap::new(),
+            }))
+    }
+
+    /// Eras with rewards still to be paid out to `stash`, see ApiFilling::unclaimed_eras
+    pub async fn unclaimed_eras(
+        &self,
+        stash: &AccountId,
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<UnclaimedPayout>> {
+        let block_hash = Some(self.pin(block_hash).await?);
+        let (active_era, current_era, history_depth) = futures::try_join!(
+            self.active_era(block_hash),
+            self.planned_era(block_hash),
+            self.decode_value::<EraIndex>(
+                &storage::value_key("Staking", "HistoryDepth"),
+                block_hash
+            ),
+        )?;
+        let first_era = current_era.saturating_sub(history_depth.unwrap_or(DEFAULT_HISTORY_DEPTH));
+        // Rewards of the active era are only known once it ends
+        let eras: Vec<EraIndex> = (first_era..active_era.index).collect();
+
+        let reward_keys: Vec<StorageKey> =
+            eras.iter().map(|e| storage::era_reward_key(*e)).collect();
+        let points_keys: Vec<StorageKey> = eras
+            .iter()
+            .map(|e| storage::reward_points_key(*e))
+            .collect();
+        let (era_rewards, points) = futures::try_join!(
+            self.decode_batch::<Balance>(&reward_keys, block_hash),
+            self.decode_batch::<EraRewardPoints<AccountId>>(&points_keys, block_hash),
+        )?;
+
+        let staker = if query::validated(stash, &points) {
+            Staker::Validator
+        } else {
+            Staker::Nominator(
+                self.decode_value(&storage::nominators_key(stash), block_hash)
+                    .await?,
+            )
+        };
+        let candidates = query::exposure_candidates(stash, &staker, &eras, &points);
+        // Payouts need the validator's ledger, which lists the eras it claimed
+        let validators = query::candidate_validators(&candidates);
+        let ledgers = self.ledgers(&validators, block_hash).await?;
+        let claimed = query::claimed_eras(&validators, ledgers);
+
+        let mut exposed = Vec::new();
+        for (era, candidates) in eras.iter().zip(candidates) {
+            let pending = query::pending(*era, candidates, &claimed);
+            let mut found = 0;
+            for chunk in pending.chunks(self.batch_size.max(1)) {
+                let keys: Vec<StorageKey> = chunk
+                    .iter()
+                    .map(|v| storage::eras_stakers_clipped_key(*era, v))
+                    .collect();
+                let exposures = self
+                    .decode_batch::<Exposure<AccountId, Balance>>(&keys, block_hash)
+                    .await?;
+                for (validator, exposure) in chunk.iter().zip(exposures) {
+                    match exposure {
+                        Some(e) if query::exposed_to(stash, validator, &e) => {
+                            exposed.push((*era, (*validator).clone(), e));
+                            found += 1;
+                        }
+                        _ => {}
+                    }
+                }
+                if found >= staker.max_exposures() {
+                    break;
+                }
+            }
+        }
+        let prefs_keys: Vec<StorageKey> = exposed
+            .iter()
+            .map(|(era, validator, _)| storage::eras_validator_prefs_key(*era, validator))
+            .collect();
+        let prefs = self
+            .decode_batch::<ValidatorPrefs>(&prefs_keys, block_hash)
+            .await?;
+
+        Ok(query::unclaimed_payouts(
+            stash,
+            first_era,
+            exposed,
+            prefs,
+            &era_rewards,
+            &points,
+        ))
+    }
+
+    /// Changes to the waiting validators and nominators from one block to another
+    pub async fn staking_diff(&self, from: Hash, to: Hash) -> Result<diff::StakingDiff> {
+        let ((validators_before, nominators_before), (validators_after, nominators_after)) =
+            futures::try_join!(self.staking_sets(from), self.staking_sets(to))?;
+        Ok(diff::diff(
+            &validators_before,
+            &nominators_before,
+            &validators_after,
+            &nominators_after,
+        ))
+    }
+
+    /// Waiting validators and nominators at `block_hash`, the nominators are read once
+    async fn staking_sets(
+        &self,
+        block_hash: Hash,
+    ) -> Result<(
+        HashMap<String, WaitingValidator>,
+        HashMap<String, Option<Nominator>>,
+    )> {
+        let block_hash = Some(block_hash);
+        let nom_list = self.get_nominators(block_hash).await?;
+        let v_to_n = nominators_by_validator(nom_list.clone());
+        futures::try_join!(
+            self.waiting_backed_by(&v_to_n, block_hash),
+            self.nominators_staked(nom_list, block_hash),
+        )
+    }
+
+    /// Metrics for the exporter, all read at the same block
+    pub async fn metrics(&self, block_hash: Option<Hash>) -> Result<metrics::Metrics> {
+        let hash = self.pin(block_hash).await?;
+        let block_hash = Some(hash);
+        let (header, active_era, planned_era, session_index) = futures::try_join!(
+            self.header(hash),
+            self.active_era(block_hash),
+            self.planned_era(block_hash),
+            self.session_index(block_hash),
+        )?;
+        let (nom_list, queued, validators) = futures::try_join!(
+            self.get_nominators(block_hash),
+            self.queued_validators(block_hash),
+            self.map_keys("Staking", "Validators", block_hash),
+        )?;
+        let nominators = nom_list.len();
+        let mut validator_nominators: Vec<(String, usize)> = nominators_by_validator(nom_list)
+            .into_iter()
+            .map(|(stash, n)| (stash.to_ss58check(), n.len()))
+            .collect();
+        validator_nominators.sort();
+        let exposures = match queued {
+            Some(r) => r
+                .exposures
+                .iter()
+                .map(|(stash, e)| (stash.to_ss58check(), e.total, e.own))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        Ok(metrics::Metrics {
+            block: header.number,
+            active_era: active_era.index,
+            planned_era,
+            session_index,
+            waiting_validators: validators.len(),
+            nominators,
+            exposures,
+            validator_nominators,
+        })
+    }
+
+    /// State of `stashes` the alert rules look at, with nominators counted from `v_to_n`
+    pub async fn stash_status(
+        &self,
+        stashes: &[AccountId],
+        v_to_n: &HashMap<AccountId, Vec<String>>,
+        block_hash: Option<Hash>,
+    ) -> Result<alert::Status> {
+        let hash = self.pin(block_hash).await?;
+        let block_hash = Some(hash);
+        let prefs_keys: Vec<StorageKey> = stashes.iter().map(storage::validators_key).collect();
+        let (header, session_index, session_validators, queued, prefs) = futures::try_join!(
+            self.header(hash),
+            self.session_index(block_hash),
+            self.session_validators(block_hash),
+            self.queued_validators(block_hash),
+            self.decode_batch::<ValidatorPrefs>(&prefs_keys, block_hash),
+        )?;
+        let online = self
+            .online(stashes, &session_validators, session_index, block_hash)
+            .await?;
+
+        let stashes = stashes
+            .iter()
+            .zip(prefs)
+            .zip(online)
+            .map(|((stash, prefs), online)| alert::StashStatus {
+                stash: stash.clone(),
+                in_session: session_validators.contains(stash),
+                queued: queued.as_ref().map(|q| q.elected_stashes.contains(stash)),
+                commission: prefs.map(|p| p.commission),
+                nominators: v_to_n.get(stash).map_or(0, |n| n.len()),
+                online,
+            })
+            .collect();
+        Ok(alert::Status {
+            block: header.number,
+            session_index,
+            stashes,
+        })
+    }
+
+    /// One page of at most `count` keys under `prefix`, starting after `start_key`
+    pub async fn keys_paged(
+        &self,
+        prefix: &StorageKey,
+        count: u32,
+        start_key: Option<&StorageKey>,
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<StorageKey>> {
+        self.source
+            .keys_paged(prefix, count, start_key, block_hash)
+            .await
+    }
+
+    /// Every key under `prefix`, `page_size` keys per `state_getKeysPaged` request
+    ///
+    /// All pages are read at the same block, the latest if none is given.
+    pub async fn keys(
+        &self,
+        prefix: StorageKey,
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<StorageKey>> {
+        let block_hash = Some(self.pin(block_hash).await?);
+        let page_size = self.page_size.max(1);
+        let mut keys = Vec::new();
+        loop {
+            let page = self
+                .keys_paged(&prefix, page_size, keys.last(), block_hash)
+                .await?;
+            let done = page.len() < page_size as usize;
+            keys.extend(page);
+            if done {
+                return Ok(keys);
+            }
+        }
+    }
+
+    /// Raw values of `keys` at the block, `batch_size` keys per request, sent concurrently
+    pub async fn storage_batch(
+        &self,
+        keys: &[StorageKey],
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        let requests = keys
+            .chunks(self.batch_size.max(1))
+            .map(|chunk| self.source.storage_batch(chunk, block_hash));
+        Ok(try_join_all(requests)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+
+    /// Decoded values of `keys`, `None` for empty entries
+    pub async fn decode_batch<V: Decode>(
+        &self,
+        keys: &[StorageKey],
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<Option<V>>> {
+        storage::decode_values(keys, self.storage_batch(keys, block_hash).await?)
+    }
+
+    /// Copies every entry dumpling reads at the block, see ApiFilling::snapshot
+    pub async fn snapshot(&self, block_hash: Option<Hash>) -> Result<MemorySource> {
+        let hash = self.pin(block_hash).await?;
+        let block_hash = Some(hash);
+        let mut snapshot = MemorySource::new(self.header(hash).await?);
+        snapshot.properties = self
+            .rpc("system_properties", json!([]))
+            .await
+            .ok()
+            .filter(|p| !p.is_null())
+            .map(|p| p.to_string());
+
+        let mut keys: Vec<StorageKey> = SNAPSHOT_VALUES
+            .iter()
+            .map(|(module, item)| storage::value_key(module, item))
+            .collect();
+        let (validators, nominators) = futures::try_join!(
+            self.map_keys("Staking", "Validators", block_hash),
+            self.map_keys("Staking", "Nominators", block_hash),
+        )?;
+        keys.extend(validators);
+        keys.extend(nominators);
+        let stashes = keys[SNAPSHOT_VALUES.len()..]
+            .iter()
+            .map(storage::account_from_key)
+            .collect::<Result<Vec<AccountId>>>()?;
+        keys.extend(stashes.iter().map(storage::locks_key));
+        let bonded_keys: Vec<StorageKey> = stashes.iter().map(storage::bonded_key).collect();
+        let controllers = self
+            .decode_batch::<AccountId>(&bonded_keys, block_hash)
+            .await?;
+        keys.extend(bonded_keys);
+        keys.extend(ledger_keys(&stashes, &controllers));
+        if let Ok(active) = self.active_era(block_hash).await {
+            keys.push(storage::reward_points_key(active.index));
+        }
+
+        let values = self.storage_batch(&keys, block_hash).await?;
+        for (key, value) in keys.into_iter().zip(values) {
+            if let Some(value) = value {
+                snapshot.insert(key, value);
+            }
+        }
+        Ok(snapshot)
+    }
+
+    async fn get_nominators(&self, block_hash: Option<Hash>) -> Result<NominatorList> {
+        let storage_keys = self.map_keys("Staking", "Nominators", block_hash).await?;
+        let noms = self
+            .decode_batch::<Nominations<AccountId>>(&storage_keys, block_hash)
+            .await?;
+        nominator_list(storage_keys, noms)
+    }
+
+    /// Pins queries spanning several requests to one block, the latest if none is given
+    async fn pin(&self, block_hash: Option<Hash>) -> Result<Hash> {
+        match block_hash {
+            Some(hash) => Ok(hash),
+            None => self.source.block_hash(None).await,
+        }
+    }
+
+    async fn storage_value<V: Decode>(
+        &self,
+        module: &str,
+        item: &str,
+        block_hash: Option<Hash>,
+    ) -> Result<V> {
+        self.decode_value(&storage::value_key(module, item), block_hash)
+            .await?
+            .ok_or_else(|| DumplingError::MissingStorage(format!("{}::{}", module, item)))
+    }
+
+    async fn decode_value<V: Decode>(
+        &self,
+        key: &StorageKey,
+        block_hash: Option<Hash>,
+    ) -> Result<Option<V>> {
+        let value = self.source.storage(key, block_hash).await?;
+        Ok(storage::decode_values(&[key.clone()], vec![value])?
+            .pop()
+            .flatten())
+    }
+
+    async fn map_keys(
+        &self,
+        module: &str,
+        item: &str,
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<StorageKey>> {
+        self.keys(storage::value_key(module, item), block_hash)
+            .await
+    }
+
+    /// Amounts locked by staking
+    async fn staked(
+        &self,
+        accounts: &[AccountId],
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<Balance>> {
+        let keys: Vec<StorageKey> = accounts.iter().map(storage::locks_key).collect();
+        Ok(self
+            .decode_batch::<Vec<BalanceLock<Balance>>>(&keys, block_hash)
+            .await?
+            .into_iter()
+            .map(|locks| staking_lock(&locks.unwrap_or_default()))
+            .collect())
+    }
+
+    /// Whether the stashes sent a heartbeat or authored a block in the session
+    async fn online(
+        &self,
+        stashes: &[AccountId],
+        session_validators: &[AccountId],
+        session_index: SessionIndex,
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<bool>> {
+        let heartbeat_keys: Vec<StorageKey> = stashes
+            .iter()
+            .map(|stash| {
+                // Heartbeats are indexed by position in the session validators
+                let auth_index = session_validators
+                    .iter()
+                    .position(|v| v == stash)
+                    .unwrap_or_else(|| session_validators.len());
+                storage::heartbeat_key(session_index, auth_index as u32)
+            })
+            .collect();
+        let authored_keys: Vec<StorageKey> = stashes
+            .iter()
+            .map(|stash| storage::authored_blocks_key(session_index, stash))
+            .collect();
+        let (heartbeats, authored) = futures::try_join!(
+            self.storage_batch(&heartbeat_keys, block_hash),
+            self.decode_batch::<u32>(&authored_keys, block_hash),
+        )?;
+        Ok(heartbeats
+            .iter()
+            .zip(authored)
+            .map(|(heartbeat, blocks)| heartbeat.is_some() || blocks.unwrap_or(0) > 0)
+            .collect())
+    }
+
+    /// Ledgers of the stashes, looked up through their bonded controllers
+    async fn ledgers(
+        &self,
+        stashes: &[AccountId],
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<Option<StakingLedger<AccountId, Balance>>>> {
+        let bonded_keys: Vec<StorageKey> = stashes.iter().map(storage::bonded_key).collect();
+        let controllers = self
+            .decode_batch::<AccountId>(&bonded_keys, block_hash)
+            .await?;
+        self.decode_batch(&ledger_keys(stashes, &controllers), block_hash)
+            .await
+    }
+}
@@ -0,0 +1,218 @@
+//! Backends AsyncApiFilling reads chain state from, enabled with the `async` feature
+
+use crate::{
+    source::endpoint, storage, BlakeTwo256, BlockNumber, DumplingError, Hash, Header, MemorySource,
+    Result, StorageKey, StorageSource,
+};
+use async_trait::async_trait;
+use jsonrpsee::{
+    core::{client::ClientT, params::ArrayParams},
+    ws_client::{WsClient, WsClientBuilder},
+};
+use serde_json::{json, Value};
+
+/// Raw access to the state of a chain as futures, see StorageSource
+#[async_trait]
+pub trait AsyncStorageSource: Send + Sync {
+    /// Raw storage value, or map entry, at `key`
+    async fn storage(&self, key: &StorageKey, block_hash: Option<Hash>) -> Result<Option<Vec<u8>>>;
+
+    /// Raw values of `keys`, in order, read in a single request where the backend allows it
+    async fn storage_batch(
+        &self,
+        keys: &[StorageKey],
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            values.push(self.storage(key, block_hash).await?);
+        }
+        Ok(values)
+    }
+
+    /// One page of at most `count` keys under `prefix`, starting after `start_key`
+    async fn keys_paged(
+        &self,
+        prefix: &StorageKey,
+        count: u32,
+        start_key: Option<&StorageKey>,
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<StorageKey>>;
+
+    async fn finalized_head(&self) -> Result<Hash>;
+
+    async fn header(&self, hash: Hash) -> Result<Header<BlockNumber, BlakeTwo256>>;
+
+    /// Hash of the block at `number`, the latest block if none is given
+    async fn block_hash(&self, number: Option<BlockNumber>) -> Result<Hash>;
+
+    /// Raw json-rpc call returning the `result` field, only node backends support it
+    async fn rpc(&self, method: &str, _params: Value) -> Result<Value> {
+        Err(DumplingError::Unsupported(format!(
+            "{} without a node connection",
+            method
+        )))
+    }
+}
+
+/// A node reached through the tokio based jsonrpsee client
+pub struct WsSource {
+    pub client: WsClient,
+}
+
+impl WsSource {
+    /// Connects to `url`, see ApiFilling::new
+    pub async fn new(url: &str) -> Result<Self> {
+        let endpoint = endpoint(url);
+        let client = WsClientBuilder::default()
+            .build(&endpoint)
+            .await
+            .map_err(|e| DumplingError::Connection(format!("{}: {}", endpoint, e)))?;
+        Ok(Self { client })
+    }
+}
+
+#[async_trait]
+impl AsyncStorageSource for WsSource {
+    async fn storage(&self, key: &StorageKey, block_hash: Option<Hash>) -> Result<Option<Vec<u8>>> {
+        match self
+            .rpc(
+                "state_getStorage",
+                json!([storage::to_hex(&key.0), storage::at_param(block_hash)]),
+            )
+            .await?
+        {
+            Value::String(value) => storage::from_hex(&value).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// One `state_queryStorageAt` request for all of `keys`
+    async fn storage_batch(
+        &self,
+        keys: &[StorageKey],
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        let hex_keys: Vec<String> = keys.iter().map(|k| storage::to_hex(&k.0)).collect();
+        let response = self
+            .rpc(
+                "state_queryStorageAt",
+                json!([hex_keys, storage::at_param(block_hash)]),
+            )
+            .await?;
+        storage::query_storage_values(keys, &response)
+    }
+
+    async fn keys_paged(
+        &self,
+        prefix: &StorageKey,
+        count: u32,
+        start_key: Option<&StorageKey>,
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<StorageKey>> {
+        let start_key = match start_key {
+            Some(k) => Value::String(storage::to_hex(&k.0)),
+            None => Value::Null,
+        };
+        let response = self
+            .rpc(
+                "state_getKeysPaged",
+                json!([
+                    storage::to_hex(&prefix.0),
+                    count,
+                    start_key,
+                    storage::at_param(block_hash)
+                ]),
+            )
+            .await?;
+        storage::keys_from_response(&response)
+    }
+
+    async fn finalized_head(&self) -> Result<Hash> {
+        match self.rpc("chain_getFinalizedHead", json!([])).await? {
+            Value::String(hash) => storage::parse_hash(&hash),
+            _ => Err(DumplingError::Connection(
+                "chain_getFinalizedHead returned nothing".to_string(),
+            )),
+        }
+    }
+
+    async fn header(&self, hash: Hash) -> Result<Header<BlockNumber, BlakeTwo256>> {
+        match self
+            .rpc("chain_getHeader", json!([storage::at_param(Some(hash))]))
+            .await?
+        {
+            Value::Null => Err(DumplingError::MissingStorage(format!(
+                "Header of {:?}",
+                hash
+            ))),
+            header => serde_json::from_value(header)
+                .map_err(|_| DumplingError::Decode(format!("header of {:?}", hash))),
+        }
+    }
+
+    async fn block_hash(&self, number: Option<BlockNumber>) -> Result<Hash> {
+        let params = match number {
+            Some(n) => json!([n]),
+            None => json!([]),
+        };
+        match self.rpc("chain_getBlockHash", params).await? {
+            Value::String(hash) => storage::parse_hash(&hash),
+            _ => Err(match number {
+                Some(n) => DumplingError::MissingStorage(format!("Block #{}", n)),
+                None => {
+                    DumplingError::Connection("chain_getBlockHash returned nothing".to_string())
+                }
+            }),
+        }
+    }
+
+    async fn rpc(&self, method: &str, params: Value) -> Result<Value> {
+        let mut rpc_params = ArrayParams::new();
+        if let Value::Array(params) = params {
+            for p in params {
+                rpc_params
+                    .insert(p)
+                    .map_err(|e| DumplingError::Encode(e.to_string()))?;
+            }
+        }
+        self.client
+            .request(method, rpc_params)
+            .await
+            .map_err(|e| DumplingError::Connection(format!("{}: {}", method, e)))
+    }
+}
+
+/// The state is in memory, every future is ready at once
+#[async_trait]
+impl AsyncStorageSource for MemorySource {
+    async fn storage(&self, key: &StorageKey, block_hash: Option<Hash>) -> Result<Option<Vec<u8>>> {
+        StorageSource::storage(self, key, block_hash)
+    }
+
+    async fn keys_paged(
+        &self,
+        prefix: &StorageKey,
+        count: u32,
+        start_key: Option<&StorageKey>,
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<StorageKey>> {
+        StorageSource::keys_paged(self, prefix, count, start_key, block_hash)
+    }
+
+    async fn finalized_head(&self) -> Result<Hash> {
+        StorageSource::finalized_head(self)
+    }
+
+    async fn header(&self, hash: Hash) -> Result<Header<BlockNumber, BlakeTwo256>> {
+        StorageSource::header(self, hash)
+    }
+
+    async fn block_hash(&self, number: Option<BlockNumber>) -> Result<Hash> {
+        StorageSource::block_hash(self, number)
+    }
+
+    async fn rpc(&self, method: &str, params: Value) -> Result<Value> {
+        StorageSource::rpc(self, method, params)
+    }
+}
@@ -0,0 +1,109 @@
+use crate::Chain;
+use polkadot_primitives::Balance;
+use serde_json::Value;
+use std::convert::TryFrom;
+
+/// Most decimals a u128 planck amount can have, `10^39` overflows it
+pub const MAX_DECIMALS: u8 = 38;
+
+/// Precision and symbol of a chain's token
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenInfo {
+    pub decimals: u8,
+    pub symbol: String,
+}
+
+impl TokenInfo {
+    /// Known defaults, used when the node does not report its `system_properties`
+    ///
+    /// Polkadot uses 10 decimals since the DOT redenomination, 12 before it.
+    pub fn for_chain(chain: Chain) -> Self {
+        let (decimals, symbol) = match chain {
+            Chain::Polkadot => (10, "DOT"),
+            Chain::Kusama => (12, "KSM"),
+            Chain::Westend => (12, "WND"),
+            Chain::Custom(_) => (12, "UNIT"),
+        };
+        Self {
+            decimals,
+            symbol: symbol.to_string(),
+        }
+    }
+
+    /// Reads `tokenDecimals` and `tokenSymbol` from a `system_properties` response,
+    /// taking the first entry of multi-token chains
+    ///
+    /// None if decimals are above MAX_DECIMALS, the node is not trusted with them.
+    pub fn from_properties(properties: &Value) -> Option<Self> {
+        let first = |v: &Value| match v {
+            Value::Array(a) => a.first().cloned(),
+            other => Some(other.clone()),
+        };
+        let decimals = first(properties.get("tokenDecimals")?)?.as_u64()?;
+        let decimals = u8::try_from(decimals).ok().filter(|d| *d <= MAX_DECIMALS)?;
+        let symbol = first(properties.get("tokenSymbol")?)?.as_str()?.to_string();
+        Some(Self { decimals, symbol })
+    }
+}
+
+/// Formats planck amounts as token amounts, e.g. `1.5 DOT`
+#[derive(Clone, Debug)]
+pub struct BalanceFormatter {
+    pub token: TokenInfo,
+    /// Print planck integers as they are
+    pub raw: bool,
+}
+
+impl BalanceFormatter {
+    pub fn new(token: TokenInfo, raw: bool) -> Self {
+        Self { token, raw }
+    }
+
+    pub fn format(&self, amount: Balance) -> String {
+        if self.raw {
+            return amount.to_string();
+        }
+        let unit = match 10u128.checked_pow(self.token.decimals as u32) {
+            Some(unit) => unit,
+            None => return amount.to_string(),
+        };
+        let whole = amount / unit;
+        let fraction = amount % unit;
+        if fraction == 0 {
+            return format!("{} {}", whole, self.token.symbol);
+        }
+        let fraction = format!("{:0width$}", fraction, width = self.token.decimals as usize);
+        format!(
+            "{}.{} {}",
+            whole,
+            fraction.trim_end_matches('0'),
+            self.token.symbol
+        )
+    }
+
+    /// Parses a token amount such as `1.5`, or planck integers when raw
+    pub fn parse(&self, amount: &str) -> Option<Balance> {
+        if self.raw {
+            return amount.parse().ok();
+        }
+        let decimals = self.token.decimals as usize;
+        let (whole, fraction) = match amount.find('.') {
+            Some(i) => (&amount[..i], &amount[i + 1..]),
+            None => (amount, ""),
+        };
+        if fraction.len() > decimals || !fraction.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let whole: Balance = if whole.is_empty() {
+            0
+        } else {
+            whole.parse().ok()?
+        };
+        let fraction: Balance = format!("{:0<width$}", fraction, width = decimals)
+            .parse()
+            .unwrap_or(0);
+        whole
+            .checked_mul(10u128.checked_pow(decimals as u32)?)?
+            .checked_add(fraction)
+    }
+}
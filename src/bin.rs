@@ -1,14 +1,110 @@
 use chrono::{TimeZone, Utc};
-use clap::{load_yaml, App, Error, ErrorKind};
+use clap::{load_yaml, App, ArgMatches, ErrorKind};
 use comfy_table::*;
-use dumpling::{ApiFilling, Nominator, Ss58Codec, WaitingValidator};
+use dumpling::{
+    alert::{self, AlertSink, CommandSink, LogSink, Rules, Status, WebhookSink},
+    balance::BalanceFormatter,
+    calls::{StakingCall, MAX_NOMINATIONS, MAX_NOMINATOR_REWARDED_PER_VALIDATOR},
+    config::{Config, Profile},
+    events::TxEvent,
+    fee::FeeInfo,
+    keystore::Keystore,
+    output::{
+        self, ActiveEraRecord, BlockRecord, ChangeRecord, ElectionResultRecord, ExposureRecord,
+        FeeRecord, KeyRecord, NominatorRecord, OutputFormat, PlannedEraRecord, RewardPointsRecord,
+        SessionIndexRecord, StashRecord, UnclaimedPayoutRecord, WaitingValidatorRecord,
+    },
+    parse_account, set_default_ss58_version,
+    signer::{self, CryptoType, Signer},
+    storage,
+    tx::{self, UnsignedTx},
+    AccountId, ApiFilling, Balance, Chain, DumplingError, Hash, MemorySource, NodeSource,
+    Nominator, Perbill, RewardDestination, Ss58Codec, StakingLedger, StorageSource,
+    WaitingValidator, DEFAULT_URL,
+};
 use rustyline::{error::ReadlineError, Editor};
+use serde::Serialize;
 use shellwords::split;
 use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
 
 pub fn main() {
-    let d = ApiFilling::new("127.0.0.1:9944", "polkadot");
     let yaml = load_yaml!("cli.yml");
+    let startup = match App::from(yaml).get_matches_from_safe(env::args().skip(1)) {
+        Ok(m) => m,
+        Err(e) => match e.kind {
+            ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed => e.exit(),
+            _ => {
+                eprintln!("{}", e.message);
+                process::exit(EXIT_USAGE);
+            }
+        },
+    };
+    let config_path = startup
+        .value_of("config")
+        .map(PathBuf::from)
+        .or_else(Config::default_path);
+    let config = match &config_path {
+        Some(path) => Config::load(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(EXIT_USAGE);
+        }),
+        None => Config::default(),
+    };
+
+    let mut profile = match arg_or_env(startup.value_of("profile"), "DUMPLING_PROFILE")
+        .or_else(|| config.default_profile.clone())
+    {
+        Some(name) => config.profile(&name).cloned().unwrap_or_else(|| {
+            eprintln!("{} is not a profile in the config file", name);
+            process::exit(EXIT_USAGE);
+        }),
+        None => Profile::default(),
+    };
+    if let Some(url) = arg_or_env(startup.value_of("url"), "DUMPLING_URL") {
+        profile.endpoint = Some(url);
+    }
+    if let Some(chain) = arg_or_env(startup.value_of("chain"), "DUMPLING_CHAIN") {
+        profile.chain = Some(chain);
+    }
+    if let Some(file) = startup.value_of("snapshot") {
+        profile.snapshot = Some(file.to_string());
+    }
+    if let Some(n) = startup.value_of("batchSize") {
+        profile.batch_size = Some(n.parse().unwrap_or_else(|_| {
+            eprintln!("--batch-size must be a number");
+            process::exit(EXIT_USAGE);
+        }));
+    }
+    if let Some(n) = startup.value_of("pageSize") {
+        profile.page_size = Some(n.parse().unwrap_or_else(|_| {
+            eprintln!("--page-size must be a number");
+            process::exit(EXIT_USAGE);
+        }));
+    }
+    let d = connect(&profile, offline(&startup)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(EXIT_FAILURE);
+    });
+    let mut ctx = Context { d, profile };
+
+    // One-shot mode: run the subcommand given on the command line and exit
+    if startup.subcommand_name().is_some() {
+        match run(&mut ctx, &config, &startup) {
+            Ok(_) => process::exit(0),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(e.exit_code());
+            }
+        }
+    }
 
     let mut wrapper = Editor::<()>::new();
     println!("Dumpling");
@@ -17,201 +113,23 @@ pub fn main() {
         match readline {
             Ok(line) => {
                 wrapper.add_history_entry(line.as_str());
-                let m = split(&line).unwrap();
+                let m = match split(&line) {
+                    Ok(m) => m,
+                    Err(e) => {
+                        println!("{}", e);
+                        continue;
+                    }
+                };
                 let matches = App::from(yaml).get_matches_from_safe(m);
 
                 match matches {
-                    Ok(result) => match result.subcommand() {
-                        ("pulse", Some(p_matches)) => {
-                            if p_matches.is_present("activeEra") {
-                                let mut t = Table::new();
-                                table_header(&mut t, vec!["Active Era Index", "Started at"], 80);
-                                let a = d.active_era(None);
-                                match a {
-                                    Some(info) => {
-                                        let time = match info.start {
-                                            Some(ts) => {
-                                                (Utc.timestamp((ts / 1000) as i64, 0)).to_rfc2822()
-                                            }
-                                            None => String::from("Era has not started yet"),
-                                        };
-                                        add_row(
-                                            &mut t,
-                                            vec![
-                                                (format!("{}", info.index), Color::Blue),
-                                                (time, Color::Blue),
-                                            ],
-                                        );
-                                        println!("{}", t);
-                                    }
-                                    None => println!("Active era information not available"),
-                                }
-                            } else if p_matches.is_present("block") {
-                                let mut t = Table::new();
-                                table_header(
-                                    &mut t,
-                                    vec!["Finalised block hash", "Finalised block number"],
-                                    160,
-                                );
-                                let b = d.finalized_head();
-                                add_row(
-                                    &mut t,
-                                    vec![
-                                        (format!("{:?}", b.0.unwrap()), Color::Blue),
-                                        (format!("{}", b.1.unwrap().number), Color::Yellow),
-                                    ],
-                                );
-                                println!("{}", t);
-                            } else if p_matches.is_present("plannedEra") {
-                                let mut t = Table::new();
-                                table_header(&mut t, vec!["Planned Era"], 80);
-                                add_row(
-                                    &mut t,
-                                    vec![(
-                                        format!("{}", d.planned_era(None).unwrap()),
-                                        Color::Blue,
-                                    )],
-                                );
-
-                                println!("{}", t);
-                            } else if p_matches.is_present("sessionIndex") {
-                                let mut t = Table::new();
-                                table_header(&mut t, vec!["Session Index"], 80);
-                                add_row(
-                                    &mut t,
-                                    vec![(
-                                        format!("{}", d.session_index(None).unwrap()),
-                                        Color::Yellow,
-                                    )],
-                                );
-
-                                println!("{}", t);
-                            } else {
-                                println!(
-                                    "{:?}",
-                                    Error {
-                                        message:
-                                            "Missing / Incorrect Arg; try --help for information"
-                                                .to_string(),
-                                        kind: ErrorKind::MissingArgumentOrSubcommand,
-                                        info: None,
-                                    }
-                                );
-                            }
-                        }
-                        ("validators", Some(v_matches)) => {
-                            if v_matches.is_present("session") {
-                                let mut t = Table::new();
-                                table_header(&mut t, vec!["Seesion Validator Stash"], 80);
-                                let v = d.session_validators(None).unwrap();
-                                for i in v {
-                                    add_row(&mut t, vec![(i.to_ss58check(), Color::Yellow)]);
-                                }
-
-                                println!("{}", t);
-                            } else if v_matches.is_present("queued") {
-                                let mut t = Table::new();
-                                table_header(
-                                    &mut t,
-                                    vec![
-                                        "Queued Validator Stash",
-                                        "Total Exposure",
-                                        "Own",
-                                        "Others (Stash key: value)",
-                                    ],
-                                    160,
-                                );
-                                if let Some(v) = d.queued_validators(None){
-                                    for i in v.exposures {
-                                        let mut fmt_exposures = HashMap::new();
-                                        let indv_exposures = (i.1).others;
-                                        for e in indv_exposures {
-                                            fmt_exposures.insert(e.who.to_ss58check(), e.value);
-                                        }
-                                        add_row(
-                                            &mut t,
-                                            vec![
-                                                (i.0.to_ss58check(), Color::Blue),
-                                                (format!("{}", (i.1).total), Color::Yellow),
-                                                (format!("{}", (i.1).own), Color::Yellow),
-                                                (format!("{:#?}", fmt_exposures), Color::Magenta),
-                                            ],
-                                        );
-                                    }
-                                    println!("{}", t);
-                                } else {
-                                    println!("None")
-                                }
-
-                            } else if v_matches.is_present("waiting") {
-                                let mut t = Table::new();
-                                table_header(
-                                    &mut t,
-                                    vec![
-                                        "Waiting Validator Stash",
-                                        "Own Staked",
-                                        "Claimed",
-                                        "Nominators",
-                                        "Commission",
-                                    ],
-                                    160,
-                                );
-                                let m = d.waiting_validators(None);
-
-                                let mut t_total = Table::new();
-                                table_header(&mut t_total, vec!["Total Waiting Validators"], 80);
-                                add_row(&mut t_total, vec![(m.keys().len().to_string(), Color::Yellow)]);
-                                println!("{}", t_total);
-
-                                match v_matches.value_of("accountId") {
-                                    Some(id) => display_validators(&mut t, &m, &id),
-                                    None => {
-                                        for i in m.keys() {
-                                            display_validators(&mut t, &m, i)
-                                        }
-                                    }
-                                }
-
-                                println!("{}", t);
-                            } else {
-                                println!("Missing / Incorrect Arg; try --help for information");
-                            }
-                        }
-                        ("nominators", Some(n_matches)) => {
-                            let mut t = Table::new();
-                            table_header(
-                                &mut t,
-                                vec![
-                                    "Nominator Stash",
-                                    "Staked",
-                                    "Nominated Validators",
-                                    "Era Submitted",
-                                    "Suppressed",
-                                ],
-                                160,
-                            );
-                            let m = d.nominators(None);
-                            let mut t_total = Table::new();
-                            table_header(&mut t_total, vec!["Total Nominators"], 80);
-                            add_row(&mut t_total, vec![(m.keys().len().to_string(), Color::Yellow)]);
-                            println!("{}", t_total);
-
-                            match n_matches.value_of("accountId") {
-                                Some(id) => display_nominators(&mut t, &m, &id),
-                                None => {
-                                    for i in m.keys() {
-                                        display_nominators(&mut t, &m, i)
-                                    }
-                                }
-                            };
-                            println!("{}", t);
-                        }
-                        ("exit", Some(_)) => {
+                    Ok(result) => match run(&mut ctx, &config, &result) {
+                        Ok(Flow::Exit) => {
                             println!("Bye!");
                             break;
                         }
-                        ("", None) => println!("No subcommand was used"),
-                        _ => unreachable!(),
+                        Ok(Flow::Continue) => {}
+                        Err(e) => println!("{}", e),
                     },
                     Err(e) => {
                         println!("{}", e);
@@ -235,6 +153,1412 @@ pub fn main() {
     }
 }
 
+/// Subcommands submitting a StakingCall
+const STAKING_COMMANDS: [&str; 8] = [
+    "bond",
+    "bond-extra",
+    "unbond",
+    "rebond",
+    "withdraw-unbonded",
+    "nominate",
+    "validate",
+    "chill",
+];
+
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_NOT_FOUND: i32 = 3;
+
+/// Longest the exporter waits on a client, scrapes are served one at a time
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether the REPL should keep reading commands
+enum Flow {
+    Continue,
+    Exit,
+}
+
+/// Errors from running a subcommand
+enum CliError {
+    /// Missing or incorrect arguments
+    Usage(String),
+    /// Requested account is not on the list
+    NotFound(String),
+    Dumpling(DumplingError),
+}
+
+impl CliError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Usage(_) => EXIT_USAGE,
+            CliError::NotFound(_) => EXIT_NOT_FOUND,
+            CliError::Dumpling(_) => EXIT_FAILURE,
+        }
+    }
+}
+
+impl From<DumplingError> for CliError {
+    fn from(e: DumplingError) -> Self {
+        CliError::Dumpling(e)
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::Usage(e) | CliError::NotFound(e) => write!(f, "{}", e),
+            CliError::Dumpling(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+fn missing_arg() -> CliError {
+    CliError::Usage("Missing / Incorrect Arg; try --help for information".to_string())
+}
+
+/// ApiFilling on a node or on a snapshot
+type Filling = ApiFilling<Box<dyn StorageSource>>;
+
+/// State shared by the REPL and one-shot mode
+struct Context {
+    d: Filling,
+    profile: Profile,
+}
+
+impl Context {
+    /// Output format from the command line, otherwise the profile's, otherwise table
+    fn output(&self, matches: &ArgMatches) -> Result<OutputFormat, CliError> {
+        match matches
+            .value_of("output")
+            .or_else(|| self.profile.output.as_deref())
+        {
+            Some(f) => f.parse().map_err(CliError::Usage),
+            None => Ok(OutputFormat::Table),
+        }
+    }
+
+    /// Formats balances in the chain's token unless `--raw` is given
+    fn balances(&self, matches: &ArgMatches) -> BalanceFormatter {
+        BalanceFormatter::new(self.d.token.clone(), matches.is_present("raw"))
+    }
+}
+
+/// Runs the subcommand once, or on every finalized block with `--watch`
+fn run(ctx: &mut Context, config: &Config, matches: &ArgMatches) -> Result<Flow, CliError> {
+    if matches.is_present("watch") {
+        let count = watch_count(matches)?;
+        watch(ctx, config, matches, count)
+    } else {
+        execute(ctx, config, matches)
+    }
+}
+
+/// Runs a single subcommand, shared by the REPL and one-shot mode
+fn execute(ctx: &mut Context, config: &Config, matches: &ArgMatches) -> Result<Flow, CliError> {
+    match matches.subcommand() {
+        ("pulse", Some(p_matches)) => pulse(&ctx.d, ctx.output(p_matches)?, p_matches)?,
+        ("validators", Some(v_matches)) => validators(
+            &ctx.d,
+            ctx.output(v_matches)?,
+            &ctx.balances(v_matches),
+            v_matches,
+        )?,
+        ("nominators", Some(n_matches)) => nominators(
+            &ctx.d,
+            ctx.output(n_matches)?,
+            &ctx.balances(n_matches),
+            n_matches,
+        )?,
+        ("rewards", Some(r_matches)) => rewards(
+            ctx,
+            ctx.output(r_matches)?,
+            &ctx.balances(r_matches),
+            r_matches,
+        )?,
+        ("payout", Some(p_matches)) => payout(ctx, &ctx.balances(p_matches), p_matches)?,
+        (name, Some(s_matches)) if STAKING_COMMANDS.contains(&name) => {
+            staking_call(ctx, name, s_matches)?
+        }
+        ("tx", Some(t_matches)) => tx(ctx, t_matches)?,
+        ("fee", Some(f_matches)) => fee(ctx, ctx.output(f_matches)?, f_matches)?,
+        ("keys", Some(k_matches)) => keys(ctx, ctx.output(k_matches)?, k_matches)?,
+        ("diff", Some(d_matches)) => diff(
+            &ctx.d,
+            ctx.output(d_matches)?,
+            &ctx.balances(d_matches),
+            d_matches,
+        )?,
+        ("daemon", Some(d_matches)) => daemon(ctx, d_matches)?,
+        ("exporter", Some(e_matches)) => exporter(&ctx.d, e_matches)?,
+        ("snapshot", Some(s_matches)) => snapshot(&ctx.d, s_matches)?,
+        ("use", Some(u_matches)) => {
+            let name = u_matches.value_of("profile").unwrap();
+            match config.profile(name) {
+                Some(p) => {
+                    ctx.d = connect(p, false)?;
+                    ctx.profile = p.clone();
+                    println!("Using profile {}", name);
+                }
+                None => {
+                    return Err(CliError::Usage(format!(
+                        "{} is not a profile in the config file",
+                        name
+                    )))
+                }
+            }
+        }
+        ("watch", Some(w_matches)) => {
+            let command: Vec<&str> = w_matches.values_of("command").unwrap().collect();
+            let yaml = load_yaml!("cli.yml");
+            let matches = App::from(yaml)
+                .get_matches_from_safe(command)
+                .map_err(|e| CliError::Usage(e.message))?;
+            return watch(ctx, config, &matches, watch_count(w_matches)?);
+        }
+        ("exit", Some(_)) => return Ok(Flow::Exit),
+        ("", None) => return Err(CliError::Usage("No subcommand was used".to_string())),
+        _ => unreachable!(),
+    }
+    Ok(Flow::Continue)
+}
+
+/// Runs the subcommand on every new finalized block, `count` times or until the
+/// subscription ends
+///
+/// Only subcommands reading the latest block are repeated. Errors are printed and the next
+/// block is waited for.
+fn watch(
+    ctx: &mut Context,
+    config: &Config,
+    matches: &ArgMatches,
+    count: Option<usize>,
+) -> Result<Flow, CliError> {
+    match matches.subcommand_name() {
+        Some("pulse") | Some("validators") | Some("nominators") => {}
+        Some(name) => return Err(CliError::Usage(format!("{} cannot be watched", name))),
+        None => return Err(CliError::Usage("No subcommand was used".to_string())),
+    }
+    let heads = ctx.d.finalized_heads()?;
+    for head in heads.take(count.unwrap_or(usize::max_value())) {
+        let head = head?;
+        println!(
+            "Block #{} {}",
+            head.number,
+            storage::to_hex(head.hash().as_bytes())
+        );
+        if let Err(e) = execute(ctx, config, matches) {
+            eprintln!("{}", e);
+        }
+    }
+    Ok(Flow::Continue)
+}
+
+/// Blocks given with `--count`, forever if none
+fn watch_count(matches: &ArgMatches) -> Result<Option<usize>, CliError> {
+    match matches.value_of("count") {
+        Some(count) => match count.parse() {
+            Ok(count) if count > 0 => Ok(Some(count)),
+            _ => Err(CliError::Usage(
+                "--count must be a positive number".to_string(),
+            )),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Evaluates the alert rules for the stashes on every new finalized block
+///
+/// Nominators are counted again when the session changes, reading them all takes a while.
+fn daemon(ctx: &Context, d_matches: &ArgMatches) -> Result<(), CliError> {
+    let addresses: Vec<String> = match d_matches.values_of("stash") {
+        Some(stashes) => stashes.map(String::from).collect(),
+        None => ctx.profile.watched.clone(),
+    };
+    if addresses.is_empty() {
+        return Err(CliError::Usage(
+            "No stash to watch, use --stash or the profile's watched accounts".to_string(),
+        ));
+    }
+    let stashes = addresses
+        .iter()
+        .map(|a| parse_account(a))
+        .collect::<Result<Vec<_>, _>>()?;
+    let rules =
+        Rules {
+            min_nominators: match d_matches.value_of("minNominators") {
+                Some(n) => Some(n.parse().map_err(|_| {
+                    CliError::Usage("--min-nominators must be a number".to_string())
+                })?),
+                None => ctx.profile.min_nominators,
+            },
+        };
+    let mut sinks: Vec<Box<dyn AlertSink>> = vec![Box::new(LogSink)];
+    if let Some(url) = arg_or_profile(d_matches.value_of("webhook"), &ctx.profile.alert_webhook) {
+        sinks.push(Box::new(WebhookSink { url }));
+    }
+    if let Some(command) = arg_or_profile(d_matches.value_of("exec"), &ctx.profile.alert_exec) {
+        sinks.push(Box::new(CommandSink { command }));
+    }
+
+    let d = &ctx.d;
+    let mut v_to_n = d.validators_to_nominators(None)?;
+    let mut prev: Option<Status> = None;
+    println!("Watching {} stashes", stashes.len());
+    for head in d.finalized_heads()? {
+        let status = head.and_then(|head| {
+            let hash = head.hash();
+            if let Some(p) = &prev {
+                if d.session_index(Some(hash))? != p.session_index {
+                    v_to_n = d.validators_to_nominators(Some(hash))?;
+                }
+            }
+            d.stash_status(&stashes, &v_to_n, Some(hash))
+        });
+        let status = match status {
+            Ok(status) => status,
+            Err(e) => {
+                eprintln!("{}", e);
+                continue;
+            }
+        };
+        if let Some(prev) = &prev {
+            for alert in alert::evaluate(prev, &status, &rules) {
+                for sink in &sinks {
+                    if let Err(e) = sink.send(&alert) {
+                        eprintln!("{}", e);
+                    }
+                }
+            }
+        }
+        prev = Some(status);
+    }
+    Ok(())
+}
+
+/// Serves `/metrics` until the process is stopped
+///
+/// Metrics are read again only when the finalized head changed since the last scrape.
+fn exporter(d: &Filling, e_matches: &ArgMatches) -> Result<(), CliError> {
+    let listen = e_matches.value_of("listen").unwrap_or("0.0.0.0:9616");
+    let listener =
+        TcpListener::bind(listen).map_err(|e| DumplingError::Io(format!("{}: {}", listen, e)))?;
+    println!("Serving metrics on http://{}/metrics", listen);
+    let mut cache: Option<(Hash, String)> = None;
+    for stream in listener.incoming() {
+        let served = stream.and_then(|stream| serve_metrics(d, stream, &mut cache));
+        if let Err(e) = served {
+            eprintln!("{}", e);
+        }
+    }
+    Ok(())
+}
+
+fn serve_metrics(
+    d: &Filling,
+    mut stream: TcpStream,
+    cache: &mut Option<(Hash, String)>,
+) -> std::io::Result<()> {
+    // A client that never sends its request must not block the next scrapes
+    stream.set_read_timeout(Some(SCRAPE_TIMEOUT))?;
+    stream.set_write_timeout(Some(SCRAPE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && header.trim() != "" {
+        header.clear();
+    }
+    let (status, body) = match request_line.split_whitespace().nth(1) {
+        Some("/metrics") => match scrape(d, cache) {
+            Ok(body) => ("200 OK", body),
+            Err(e) => ("503 Service Unavailable", format!("{}\n", e)),
+        },
+        _ => (
+            "404 Not Found",
+            "Metrics are served on /metrics\n".to_string(),
+        ),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+fn scrape(d: &Filling, cache: &mut Option<(Hash, String)>) -> Result<String, DumplingError> {
+    let (hash, _) = d.finalized_head()?;
+    if let Some((cached, body)) = cache {
+        if *cached == hash {
+            return Ok(body.clone());
+        }
+    }
+    let body = d.metrics(Some(hash))?.render();
+    *cache = Some((hash, body.clone()));
+    Ok(body)
+}
+
+/// Block given with `--at`, `None` queries the latest block
+fn block_at(d: &Filling, matches: &ArgMatches) -> Result<Option<Hash>, CliError> {
+    match matches.value_of("at") {
+        Some(at) => Ok(Some(d.resolve_block(at)?)),
+        None => Ok(None),
+    }
+}
+
+/// Prints `value` unless the table format was chosen, returns whether it was printed
+fn print_structured<T: Serialize>(format: OutputFormat, value: &T) -> Result<bool, CliError> {
+    if format == OutputFormat::Table {
+        return Ok(false);
+    }
+    println!("{}", output::render(format, value)?);
+    Ok(true)
+}
+
+fn pulse(d: &Filling, format: OutputFormat, p_matches: &ArgMatches) -> Result<(), CliError> {
+    let at = block_at(d, p_matches)?;
+    if p_matches.is_present("activeEra") {
+        let info = d.active_era(at)?;
+        if print_structured(format, &ActiveEraRecord::from(&info))? {
+            return Ok(());
+        }
+        let mut t = Table::new();
+        table_header(&mut t, vec!["Active Era Index", "Started at"], 80);
+        let time = match info.start {
+            Some(ts) => (Utc.timestamp((ts / 1000) as i64, 0)).to_rfc2822(),
+            None => String::from("Era has not started yet"),
+        };
+        add_row(
+            &mut t,
+            vec![
+                (format!("{}", info.index), Color::Blue),
+                (time, Color::Blue),
+            ],
+        );
+        println!("{}", t);
+    } else if p_matches.is_present("block") {
+        let (hash, header) = match at {
+            Some(hash) => (hash, d.header(hash)?),
+            None => d.finalized_head()?,
+        };
+        let block = BlockRecord {
+            hash: format!("{:?}", hash),
+            number: header.number,
+        };
+        if print_structured(format, &block)? {
+            return Ok(());
+        }
+        let mut t = Table::new();
+        let headers = match at {
+            Some(_) => vec!["Block hash", "Block number"],
+            None => vec!["Finalised block hash", "Finalised block number"],
+        };
+        table_header(&mut t, headers, 160);
+        add_row(
+            &mut t,
+            vec![
+                (block.hash, Color::Blue),
+                (format!("{}", block.number), Color::Yellow),
+            ],
+        );
+        println!("{}", t);
+    } else if p_matches.is_present("plannedEra") {
+        let era = PlannedEraRecord {
+            planned_era: d.planned_era(at)?,
+        };
+        if print_structured(format, &era)? {
+            return Ok(());
+        }
+        let mut t = Table::new();
+        table_header(&mut t, vec!["Planned Era"], 80);
+        add_row(&mut t, vec![(format!("{}", era.planned_era), Color::Blue)]);
+        println!("{}", t);
+    } else if p_matches.is_present("sessionIndex") {
+        let session = SessionIndexRecord {
+            session_index: d.session_index(at)?,
+        };
+        if print_structured(format, &session)? {
+            return Ok(());
+        }
+        let mut t = Table::new();
+        table_header(&mut t, vec!["Session Index"], 80);
+        add_row(
+            &mut t,
+            vec![(format!("{}", session.session_index), Color::Yellow)],
+        );
+        println!("{}", t);
+    } else {
+        return Err(missing_arg());
+    }
+    Ok(())
+}
+
+fn validators(
+    d: &Filling,
+    format: OutputFormat,
+    fmt: &BalanceFormatter,
+    v_matches: &ArgMatches,
+) -> Result<(), CliError> {
+    let at = block_at(d, v_matches)?;
+    if v_matches.is_present("session") {
+        let stashes: Vec<StashRecord> = d
+            .session_validators(at)?
+            .iter()
+            .map(|i| StashRecord {
+                stash: i.to_ss58check(),
+            })
+            .collect();
+        if print_structured(format, &stashes)? {
+            return Ok(());
+        }
+        let mut t = Table::new();
+        table_header(&mut t, vec!["Seesion Validator Stash"], 80);
+        for i in stashes {
+            add_row(&mut t, vec![(i.stash, Color::Yellow)]);
+        }
+        println!("{}", t);
+    } else if v_matches.is_present("queued") {
+        let queued = match d.queued_validators(at)? {
+            Some(v) => ElectionResultRecord::new(&v, fmt),
+            None => {
+                let printed = match format {
+                    OutputFormat::Csv => {
+                        println!("{}", ExposureRecord::COLUMNS.join(","));
+                        true
+                    }
+                    _ => print_structured(format, &None::<ElectionResultRecord>)?,
+                };
+                if !printed {
+                    println!("None");
+                }
+                return Ok(());
+            }
+        };
+        let printed = match format {
+            // csv has one row per exposure
+            OutputFormat::Csv => print_structured(format, &queued.exposures)?,
+            _ => print_structured(format, &queued)?,
+        };
+        if printed {
+            return Ok(());
+        }
+        let mut t = Table::new();
+        table_header(
+            &mut t,
+            vec![
+                "Queued Validator Stash",
+                "Total Exposure",
+                "Own",
+                "Others (Stash key: value)",
+            ],
+            160,
+        );
+        for i in queued.exposures {
+            let others: Vec<String> = i
+                .others
+                .iter()
+                .map(|e| format!("{}: {}", e.who, e.value))
+                .collect();
+            add_row(
+                &mut t,
+                vec![
+                    (i.stash, Color::Blue),
+                    (i.total, Color::Yellow),
+                    (i.own, Color::Yellow),
+                    (others.join("\n"), Color::Magenta),
+                ],
+            );
+        }
+        println!("{}", t);
+    } else if v_matches.is_present("waiting") {
+        let m = d.waiting_validators(at)?;
+        let records = match v_matches.value_of("accountId") {
+            Some(id) => vec![waiting_validator_record(&m, id, fmt)?],
+            None => {
+                let mut records: Vec<WaitingValidatorRecord> = m
+                    .iter()
+                    .map(|(stash, v)| WaitingValidatorRecord::new(stash, v, fmt))
+                    .collect();
+                records.sort_by(|a, b| a.stash.cmp(&b.stash));
+                records
+            }
+        };
+        if print_structured(format, &records)? {
+            return Ok(());
+        }
+
+        let mut t_total = Table::new();
+        table_header(&mut t_total, vec!["Total Waiting Validators"], 80);
+        add_row(
+            &mut t_total,
+            vec![(m.keys().len().to_string(), Color::Yellow)],
+        );
+        println!("{}", t_total);
+
+        let mut t = Table::new();
+        table_header(
+            &mut t,
+            vec![
+                "Waiting Validator Stash",
+                "Own Staked",
+                "Claimed",
+                "Nominators",
+                "Commission",
+            ],
+            160,
+        );
+        for r in records {
+            display_validator(&mut t, r);
+        }
+        println!("{}", t);
+    } else {
+        return Err(missing_arg());
+    }
+    Ok(())
+}
+
+fn nominators(
+    d: &Filling,
+    format: OutputFormat,
+    fmt: &BalanceFormatter,
+    n_matches: &ArgMatches,
+) -> Result<(), CliError> {
+    let at = block_at(d, n_matches)?;
+    let m = d.nominators(at)?;
+    let records: Vec<NominatorRecord> = match n_matches.value_of("accountId") {
+        Some(id) => nominator_record(&m, id, fmt)?.into_iter().collect(),
+        None => {
+            let mut records: Vec<NominatorRecord> = m
+                .iter()
+                .filter_map(|(stash, n)| n.as_ref().map(|n| NominatorRecord::new(stash, n, fmt)))
+                .collect();
+            records.sort_by(|a, b| a.stash.cmp(&b.stash));
+            records
+        }
+    };
+    if print_structured(format, &records)? {
+        return Ok(());
+    }
+
+    let mut t_total = Table::new();
+    table_header(&mut t_total, vec!["Total Nominators"], 80);
+    add_row(
+        &mut t_total,
+        vec![(m.keys().len().to_string(), Color::Yellow)],
+    );
+    println!("{}", t_total);
+
+    let mut t = Table::new();
+    table_header(
+        &mut t,
+        vec![
+            "Nominator Stash",
+            "Staked",
+            "Nominated Validators",
+            "Era Submitted",
+            "Suppressed",
+        ],
+        160,
+    );
+    for r in records {
+        display_nominator(&mut t, r);
+    }
+    println!("{}", t);
+    Ok(())
+}
+
+fn rewards(
+    ctx: &Context,
+    format: OutputFormat,
+    fmt: &BalanceFormatter,
+    r_matches: &ArgMatches,
+) -> Result<(), CliError> {
+    let d = &ctx.d;
+    let at = block_at(d, r_matches)?;
+    if let Some(stash) = r_matches.value_of("unclaimed") {
+        let records: Vec<UnclaimedPayoutRecord> = d
+            .unclaimed_eras(&parse_account(stash)?, at)?
+            .iter()
+            .map(|p| UnclaimedPayoutRecord::new(p, fmt))
+            .collect();
+        if print_structured(format, &records)? {
+            return Ok(());
+        }
+        if records.is_empty() {
+            println!("Nothing to claim");
+            return Ok(());
+        }
+        let mut t = Table::new();
+        table_header(
+            &mut t,
+            vec!["Era", "Validator Stash", "Estimated Payout"],
+            120,
+        );
+        for r in records {
+            add_row(
+                &mut t,
+                vec![
+                    (format!("{}", r.era), Color::Blue),
+                    (r.validator, Color::Blue),
+                    (r.payout, Color::Yellow),
+                ],
+            );
+        }
+        println!("{}", t);
+        return Ok(());
+    }
+    if !r_matches.is_present("points") {
+        return Err(missing_arg());
+    }
+    let era = match r_matches.value_of("era") {
+        Some(era) => era
+            .parse()
+            .map_err(|_| CliError::Usage("--era must be a number".to_string()))?,
+        None => d.active_era(at)?.index,
+    };
+    let mut watched = ctx.profile.watched.clone();
+    if let Some(stashes) = r_matches.values_of("highlight") {
+        watched.extend(stashes.map(String::from));
+    }
+    let watched = watched
+        .iter()
+        .map(|a| parse_account(a))
+        .collect::<Result<Vec<_>, _>>()?;
+    let record = RewardPointsRecord::new(era, &d.era_reward_points(era, at)?, &watched);
+    let printed = match format {
+        // csv has one row per validator
+        OutputFormat::Csv => print_structured(format, &record.validators)?,
+        _ => print_structured(format, &record)?,
+    };
+    if printed {
+        return Ok(());
+    }
+    let mut t = Table::new();
+    table_header(
+        &mut t,
+        vec!["Rank", "Validator Stash", "Points", "Share (%)"],
+        120,
+    );
+    for v in record.validators {
+        let color = if v.watched { Color::Green } else { Color::Blue };
+        add_row(
+            &mut t,
+            vec![
+                (format!("{}", v.rank), color),
+                (v.stash, color),
+                (format!("{}", v.points), Color::Yellow),
+                (format!("{}", v.percent), Color::Yellow),
+            ],
+        );
+    }
+    println!("Era {}: {} points", record.era, record.total);
+    println!("{}", t);
+    Ok(())
+}
+
+fn payout(ctx: &Context, fmt: &BalanceFormatter, p_matches: &ArgMatches) -> Result<(), CliError> {
+    let d = &ctx.d;
+    let stash = parse_account(p_matches.value_of("stash").ok_or_else(missing_arg)?)?;
+    let unclaimed = d.unclaimed_eras(&stash, None)?;
+    let pending: Vec<_> = match p_matches.value_of("era") {
+        Some(era) => {
+            let era: u32 = era
+                .parse()
+                .map_err(|_| CliError::Usage("--era must be a number".to_string()))?;
+            unclaimed.into_iter().filter(|p| p.era == era).collect()
+        }
+        None if p_matches.is_present("all") => unclaimed,
+        None => return Err(CliError::Usage("Use --era ERA or --all".to_string())),
+    };
+    if pending.is_empty() {
+        return Err(CliError::NotFound(format!(
+            "Nothing to pay out for {}",
+            stash.to_ss58check()
+        )));
+    }
+    for p in &pending {
+        println!(
+            "Era {} of {}, about {}",
+            p.era,
+            p.validator.to_ss58check(),
+            fmt.format(p.payout)
+        );
+    }
+    let payouts: Vec<_> = pending
+        .iter()
+        .map(|p| (p.validator.clone(), p.era))
+        .collect();
+    let account = signing_account(ctx, p_matches)?;
+    println!("payout_stakers signed by {}", account.to_ss58check());
+    confirm_and_submit(
+        ctx,
+        p_matches,
+        fmt,
+        "payout_stakers",
+        &account,
+        || d.encode_payout_call(&payouts),
+        |key| d.sign_payout_stakers(key, &payouts),
+    )
+}
+
+/// Composes a staking call, shows what it changes, then submits it once confirmed
+fn staking_call(ctx: &Context, command: &str, s_matches: &ArgMatches) -> Result<(), CliError> {
+    let d = &ctx.d;
+    let fmt = ctx.balances(s_matches);
+    let call = parse_staking_call(command, s_matches, &fmt)?;
+    if let StakingCall::Nominate { targets } = &call {
+        check_nominations(d, targets)?;
+    }
+    let account = signing_account(ctx, s_matches)?;
+    println!("{} signed by {}", call.name(), account.to_ss58check());
+    match &call {
+        StakingCall::Nominate { targets } => {
+            for target in targets {
+                println!("  target {}", target.to_ss58check());
+            }
+        }
+        StakingCall::Validate { commission } => {
+            println!("  commission {}%", output::commission_percent(*commission))
+        }
+        StakingCall::Chill => println!("  stops nominating or validating from the next era"),
+        _ => {}
+    }
+    if call.changes_ledger() {
+        let before = d.ledger_for(&call, &account, None)?;
+        let after = call.predict(&account, before.as_ref(), d.planned_era(None)?);
+        println!("{}", ledger_table(&fmt, before.as_ref(), after.as_ref()));
+    }
+
+    confirm_and_submit(
+        ctx,
+        s_matches,
+        &fmt,
+        call.name(),
+        &account,
+        || d.encode_staking_call(&call),
+        |key| d.sign_staking_call(key, &call),
+    )
+}
+
+/// Signs a call of `account` and submits it once confirmed, unless `--yes` is given
+///
+/// `--dry-run` only prints the encoded call and its estimated fee, without unlocking the key,
+/// so `account` may come from `--from` without any signer.
+fn confirm_and_submit(
+    ctx: &Context,
+    matches: &ArgMatches,
+    fmt: &BalanceFormatter,
+    name: &str,
+    account: &AccountId,
+    encode: impl FnOnce() -> Result<Vec<u8>, DumplingError>,
+    sign: impl FnOnce(&Signer) -> Result<String, DumplingError>,
+) -> Result<(), CliError> {
+    let d = &ctx.d;
+    if matches.is_present("dryRun") {
+        let encoded = encode()?;
+        print_fee(d, fmt, &d.call_fee(&encoded)?, Some(account))?;
+        println!("Call: {}", storage::to_hex(&encoded));
+        return Ok(());
+    }
+    let key = signing_key(ctx, matches)?;
+    if &key.account() != account {
+        return Err(CliError::Usage(format!(
+            "The signing key is {}, not {}",
+            key.account().to_ss58check(),
+            account.to_ss58check()
+        )));
+    }
+    let extrinsic = sign(&key)?;
+    show_fee(d, fmt, &extrinsic, Some(account))?;
+    if !matches.is_present("yes") && !confirm(&format!("Submit {}?", name))? {
+        println!("Not submitted");
+        return Ok(());
+    }
+    submit(d, fmt, extrinsic)
+}
+
+/// StakingCall of a staking subcommand and its arguments
+fn parse_staking_call(
+    command: &str,
+    s_matches: &ArgMatches,
+    fmt: &BalanceFormatter,
+) -> Result<StakingCall, CliError> {
+    let value = || -> Result<Balance, CliError> {
+        let value = s_matches.value_of("value").ok_or_else(missing_arg)?;
+        fmt.parse(value).ok_or_else(|| {
+            CliError::Usage(format!(
+                "{} is not an amount of {}",
+                value, fmt.token.symbol
+            ))
+        })
+    };
+    Ok(match command {
+        "bond" => StakingCall::Bond {
+            controller: parse_account(s_matches.value_of("controller").ok_or_else(missing_arg)?)?,
+            value: value()?,
+            payee: match s_matches.value_of("payee") {
+                Some("stash") => RewardDestination::Stash,
+                Some("controller") => RewardDestination::Controller,
+                _ => RewardDestination::Staked,
+            },
+        },
+        "bond-extra" => StakingCall::BondExtra { value: value()? },
+        "unbond" => StakingCall::Unbond { value: value()? },
+        "rebond" => StakingCall::Rebond { value: value()? },
+        "nominate" => StakingCall::Nominate {
+            targets: s_matches
+                .values_of("targets")
+                .ok_or_else(missing_arg)?
+                .map(parse_account)
+                .collect::<Result<Vec<_>, _>>()?,
+        },
+        "validate" => {
+            let commission = s_matches.value_of("commission").ok_or_else(missing_arg)?;
+            match commission.parse::<f64>() {
+                Ok(pct) if (0.0..=100.0).contains(&pct) => StakingCall::Validate {
+                    commission: Perbill::from_parts((pct * 10_000_000.0).round() as u32),
+                },
+                _ => {
+                    return Err(CliError::Usage(
+                        "--commission must be a percentage from 0 to 100".to_string(),
+                    ))
+                }
+            }
+        }
+        "chill" => StakingCall::Chill,
+        "withdraw-unbonded" => StakingCall::WithdrawUnbonded,
+        _ => {
+            return Err(CliError::Usage(format!(
+                "{} is not a staking call",
+                command
+            )))
+        }
+    })
+}
+
+/// Builds an extrinsic to sign offline, signs it, or submits it
+fn tx(ctx: &Context, t_matches: &ArgMatches) -> Result<(), CliError> {
+    let d = &ctx.d;
+    match t_matches.subcommand() {
+        ("build", Some(b_matches)) => {
+            let from = parse_account(b_matches.value_of("from").ok_or_else(missing_arg)?)?;
+            let mortal_for: u64 = b_matches
+                .value_of("mortalFor")
+                .unwrap_or("4096")
+                .parse()
+                .map_err(|_| CliError::Usage("--mortal-for must be a number".to_string()))?;
+            let call = trailing_staking_call(ctx, b_matches)?;
+            if let StakingCall::Nominate { targets } = &call {
+                check_nominations(d, targets)?;
+            }
+            let unsigned = d.build_tx(&from, &d.encode_staking_call(&call)?, mortal_for)?;
+            let json = serde_json::to_string_pretty(&unsigned)
+                .map_err(|e| DumplingError::Encode(e.to_string()))?;
+            match b_matches.value_of("out") {
+                Some(file) => {
+                    write_file(file, &json)?;
+                    println!(
+                        "{} of {} with nonce {} written to {}",
+                        call.name(),
+                        unsigned.signer,
+                        unsigned.nonce,
+                        file
+                    );
+                }
+                None => println!("{}", json),
+            }
+        }
+        ("sign", Some(s_matches)) => {
+            let file = s_matches.value_of("file").ok_or_else(missing_arg)?;
+            let unsigned: UnsignedTx = serde_json::from_str(&read_file(file)?)
+                .map_err(|e| DumplingError::Decode(format!("{}: {}", file, e)))?;
+            let extrinsic = unsigned.sign(&signing_key(ctx, s_matches)?)?;
+            match s_matches.value_of("out") {
+                Some(out) => {
+                    write_file(out, &extrinsic)?;
+                    println!("Signed extrinsic written to {}", out);
+                }
+                None => println!("{}", extrinsic),
+            }
+        }
+        ("submit", Some(s_matches)) => {
+            let extrinsic = s_matches.value_of("extrinsic").ok_or_else(missing_arg)?;
+            let extrinsic = if extrinsic.starts_with("0x") {
+                extrinsic.to_string()
+            } else {
+                read_file(extrinsic)?.trim().to_string()
+            };
+            println!(
+                "Extrinsic {}",
+                storage::to_hex(tx::extrinsic_hash(&extrinsic)?.as_bytes())
+            );
+            let fmt = ctx.balances(s_matches);
+            show_fee(d, &fmt, &extrinsic, None)?;
+            submit(d, &fmt, extrinsic)?;
+        }
+        _ => return Err(missing_arg()),
+    }
+    Ok(())
+}
+
+/// StakingCall of the staking subcommand in the trailing `command` arguments
+fn trailing_staking_call(ctx: &Context, matches: &ArgMatches) -> Result<StakingCall, CliError> {
+    let command: Vec<&str> = matches
+        .values_of("command")
+        .ok_or_else(missing_arg)?
+        .collect();
+    let yaml = load_yaml!("cli.yml");
+    let matches = App::from(yaml)
+        .get_matches_from_safe(command)
+        .map_err(|e| CliError::Usage(e.message))?;
+    match matches.subcommand() {
+        (name, Some(c_matches)) if STAKING_COMMANDS.contains(&name) => {
+            parse_staking_call(name, c_matches, &ctx.balances(c_matches))
+        }
+        (name, _) => Err(CliError::Usage(format!(
+            "{} is not a staking call, use one of {}",
+            name,
+            STAKING_COMMANDS.join(", ")
+        ))),
+    }
+}
+
+/// Weight and fee of a staking call, before it is signed
+fn fee(ctx: &Context, format: OutputFormat, f_matches: &ArgMatches) -> Result<(), CliError> {
+    let d = &ctx.d;
+    let fmt = ctx.balances(f_matches);
+    let call = trailing_staking_call(ctx, f_matches)?;
+    let record = FeeRecord::new(
+        call.name(),
+        &d.call_fee(&d.encode_staking_call(&call)?)?,
+        &fmt,
+    );
+    if print_structured(format, &record)? {
+        return Ok(());
+    }
+    println!(
+        "{}: {}, weight {} ({})",
+        record.call, record.partial_fee, record.weight, record.class
+    );
+    Ok(())
+}
+
+/// Prints the fee of a signed extrinsic, see print_fee
+fn show_fee(
+    d: &Filling,
+    fmt: &BalanceFormatter,
+    extrinsic: &str,
+    signer: Option<&AccountId>,
+) -> Result<(), CliError> {
+    print_fee(d, fmt, &d.query_fee(extrinsic)?, signer)
+}
+
+/// Prints a fee, warns when the signer cannot pay it
+fn print_fee(
+    d: &Filling,
+    fmt: &BalanceFormatter,
+    fee: &FeeInfo,
+    signer: Option<&AccountId>,
+) -> Result<(), CliError> {
+    println!(
+        "Estimated fee: {}, weight {} ({})",
+        fmt.format(fee.partial_fee),
+        fee.weight,
+        fee.class
+    );
+    if let Some(signer) = signer {
+        let free = d
+            .account_info(signer, None)?
+            .map_or(0, |info| info.data.free);
+        if fee.partial_fee > free {
+            println!(
+                "Warning: {} only has {} free to pay it",
+                signer.to_ss58check(),
+                fmt.format(free)
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Submits a signed extrinsic, prints its status until it is finalized, then its events
+///
+/// An extrinsic finalized with `ExtrinsicFailed` is an error. Events that cannot be read are
+/// only a warning, the extrinsic is finalized by then and must not be submitted again.
+fn submit(d: &Filling, fmt: &BalanceFormatter, extrinsic: String) -> Result<(), CliError> {
+    // The progress prints the finalized block before the events are read
+    let block = d.submit_and_track(extrinsic.clone(), |progress| println!("{}", progress))?;
+    let outcome = match d.extrinsic_outcome(&extrinsic, block) {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            println!(
+                "Warning: finalized in block {}, but its events could not be read: {}",
+                storage::to_hex(block.as_bytes()),
+                e
+            );
+            return Ok(());
+        }
+    };
+    for event in &outcome.events {
+        match event {
+            TxEvent::Success => println!("  System::ExtrinsicSuccess"),
+            TxEvent::Failed(error) => println!("  System::ExtrinsicFailed: {}", error),
+            TxEvent::Bonded(stash, value) => println!(
+                "  Staking::Bonded {} by {}",
+                fmt.format(*value),
+                stash.to_ss58check()
+            ),
+            TxEvent::Reward(stash, value) => println!(
+                "  Staking::Reward {} to {}",
+                fmt.format(*value),
+                stash.to_ss58check()
+            ),
+            TxEvent::Other(module, name) => println!("  {}::{}", module, name),
+        }
+    }
+    match outcome.error() {
+        Some(error) => Err(CliError::Dumpling(DumplingError::Extrinsic(format!(
+            "extrinsic {} of block {} failed: {}",
+            outcome.index,
+            storage::to_hex(block.as_bytes()),
+            error
+        )))),
+        None => Ok(()),
+    }
+}
+
+fn read_file(file: &str) -> Result<String, CliError> {
+    Ok(std::fs::read_to_string(file).map_err(|e| DumplingError::Io(format!("{}: {}", file, e)))?)
+}
+
+fn write_file(file: &str, content: &str) -> Result<(), CliError> {
+    Ok(std::fs::write(file, content).map_err(|e| DumplingError::Io(format!("{}: {}", file, e)))?)
+}
+
+/// Refuses targets the call would fail with, warns about oversubscribed ones
+fn check_nominations(d: &Filling, targets: &[AccountId]) -> Result<(), CliError> {
+    let check = d.check_nominations(targets, None)?;
+    for (target, nominators) in &check.oversubscribed {
+        println!(
+            "Warning: {} is oversubscribed, {} nominators for {} paid out",
+            target.to_ss58check(),
+            nominators,
+            MAX_NOMINATOR_REWARDED_PER_VALIDATOR
+        );
+    }
+    if check.passed() {
+        return Ok(());
+    }
+    if check.too_many {
+        return Err(CliError::Usage(format!(
+            "{} targets, at most {} can be nominated",
+            targets.len(),
+            MAX_NOMINATIONS
+        )));
+    }
+    let unknown: Vec<String> = check.unknown.iter().map(|t| t.to_ss58check()).collect();
+    Err(CliError::NotFound(format!(
+        "Not validator candidates: {}",
+        unknown.join(", ")
+    )))
+}
+
+fn ledger_table(
+    fmt: &BalanceFormatter,
+    before: Option<&StakingLedger<AccountId, Balance>>,
+    after: Option<&StakingLedger<AccountId, Balance>>,
+) -> Table {
+    let cells = |ledger: Option<&StakingLedger<AccountId, Balance>>| match ledger {
+        Some(l) => vec![
+            l.stash.to_ss58check(),
+            fmt.format(l.total),
+            fmt.format(l.active),
+            l.unlocking
+                .iter()
+                .map(|c| format!("{} at era {}", fmt.format(c.value), c.era))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ],
+        None => vec!["not bonded".to_string(), "".into(), "".into(), "".into()],
+    };
+    let mut t = Table::new();
+    table_header(&mut t, vec!["Ledger", "Before", "After"], 120);
+    for ((name, b), a) in ["Stash", "Total", "Active", "Unlocking"]
+        .iter()
+        .zip(cells(before))
+        .zip(cells(after))
+    {
+        add_row(
+            &mut t,
+            vec![
+                (name.to_string(), Color::Blue),
+                (b, Color::Yellow),
+                (a, Color::Green),
+            ],
+        );
+    }
+    t
+}
+
+/// Asks on stdin, anything but y or yes is a no
+fn confirm(question: &str) -> Result<bool, CliError> {
+    print!("{} [y/N] ", question);
+    let mut answer = String::new();
+    std::io::stdout()
+        .flush()
+        .and_then(|_| std::io::stdin().read_line(&mut answer))
+        .map_err(|e| DumplingError::Io(e.to_string()))?;
+    let answer = answer.trim();
+    Ok(answer == "y" || answer == "yes")
+}
+
+/// Key signing extrinsics, from the keystore with `--key`, or from `--seed-file`,
+/// falling back to the profile's `key` then `seed_file`
+fn signing_key(ctx: &Context, matches: &ArgMatches) -> Result<Signer, CliError> {
+    if let Some(key) = matches.value_of("key") {
+        return unlock(ctx, key);
+    }
+    if let Some(file) = matches.value_of("seedFile") {
+        return Ok(signer::signer_from_file(Path::new(file))?);
+    }
+    match (&ctx.profile.key, &ctx.profile.seed_file) {
+        (Some(key), _) => unlock(ctx, key),
+        (None, Some(file)) => Ok(signer::signer_from_file(Path::new(file))?),
+        (None, None) => Err(CliError::Usage(
+            "No signer, use --key, --seed-file or the profile's key or seed_file".to_string(),
+        )),
+    }
+}
+
+/// Account signing a call, `--from` or the account of the signing key, see signing_key,
+/// without unlocking a keystore key
+fn signing_account(ctx: &Context, matches: &ArgMatches) -> Result<AccountId, CliError> {
+    if let Some(from) = matches.value_of("from") {
+        return Ok(parse_account(from)?);
+    }
+    let find = |key: &str| -> Result<AccountId, CliError> {
+        Ok(keystore(&ctx.profile)?.find(key)?.account)
+    };
+    if let Some(key) = matches.value_of("key") {
+        return find(key);
+    }
+    if matches.value_of("seedFile").is_none() {
+        if let Some(key) = &ctx.profile.key {
+            return find(key);
+        }
+    }
+    // Seed files are read without a prompt
+    Ok(signing_key(ctx, matches)?.account())
+}
+
+fn unlock(ctx: &Context, key: &str) -> Result<Signer, CliError> {
+    let keystore = keystore(&ctx.profile)?;
+    let entry = keystore.find(key)?;
+    let password = password(&format!("Password of {}: ", entry.name))?;
+    Ok(keystore.unlock(key, &password)?)
+}
+
+/// The profile's `keystore` directory, or the default one
+fn keystore(profile: &Profile) -> Result<Keystore, CliError> {
+    match profile
+        .keystore
+        .clone()
+        .map(PathBuf::from)
+        .or_else(Keystore::default_dir)
+    {
+        Some(dir) => Ok(Keystore::new(dir)),
+        None => Err(CliError::Usage(
+            "No home directory, set the profile's keystore".to_string(),
+        )),
+    }
+}
+
+/// `DUMPLING_PASSWORD`, or asked without echo
+fn password(prompt: &str) -> Result<String, CliError> {
+    if let Ok(password) = env::var("DUMPLING_PASSWORD") {
+        return Ok(password);
+    }
+    rpassword::read_password_from_tty(Some(prompt))
+        .map_err(|e| CliError::Dumpling(DumplingError::Io(e.to_string())))
+}
+
+/// Secret uri of a key to import, from stdin with `--uri-stdin` or asked without echo
+///
+/// Never `DUMPLING_PASSWORD`, which is the password the key is encrypted with.
+fn secret_uri(matches: &ArgMatches) -> Result<String, CliError> {
+    let failed = |e: std::io::Error| CliError::Dumpling(DumplingError::Io(e.to_string()));
+    if matches.is_present("uriStdin") {
+        let mut uri = String::new();
+        std::io::stdin().read_line(&mut uri).map_err(failed)?;
+        return Ok(uri);
+    }
+    rpassword::read_password_from_tty(Some("Mnemonic, 0x seed or secret uri: ")).map_err(failed)
+}
+
+fn keys(ctx: &Context, format: OutputFormat, k_matches: &ArgMatches) -> Result<(), CliError> {
+    let keystore = keystore(&ctx.profile)?;
+    match k_matches.subcommand() {
+        ("import", Some(i_matches)) => {
+            let name = i_matches.value_of("name").ok_or_else(missing_arg)?;
+            let entry = match i_matches.value_of("json") {
+                Some(file) => {
+                    let json = std::fs::read_to_string(file)
+                        .map_err(|e| DumplingError::Io(format!("{}: {}", file, e)))?;
+                    keystore.import_json(&json, name, &password("Password of the export: ")?)?
+                }
+                None => {
+                    let crypto: CryptoType =
+                        i_matches.value_of("crypto").unwrap_or("sr25519").parse()?;
+                    let signer = Signer::from_uri(secret_uri(i_matches)?.trim(), crypto)?;
+                    let password = password("New password: ")?;
+                    if env::var("DUMPLING_PASSWORD").is_err()
+                        && password != self::password("Repeat password: ")?
+                    {
+                        return Err(CliError::Usage("Passwords do not match".to_string()));
+                    }
+                    keystore.add(&signer, name, &password)?
+                }
+            };
+            println!(
+                "Imported {} {} ({})",
+                entry.name,
+                entry.account.to_ss58check(),
+                entry.crypto
+            );
+        }
+        _ => {
+            let records: Vec<KeyRecord> = keystore.list()?.iter().map(KeyRecord::new).collect();
+            if print_structured(format, &records)? {
+                return Ok(());
+            }
+            if records.is_empty() {
+                println!("No keys in {}", keystore.dir.display());
+                return Ok(());
+            }
+            let mut t = Table::new();
+            table_header(&mut t, vec!["Name", "Account", "Crypto"], 120);
+            for r in records {
+                add_row(
+                    &mut t,
+                    vec![
+                        (r.name, Color::Green),
+                        (r.account, Color::Blue),
+                        (r.crypto, Color::Yellow),
+                    ],
+                );
+            }
+            println!("{}", t);
+        }
+    }
+    Ok(())
+}
+
+fn diff(
+    d: &Filling,
+    format: OutputFormat,
+    fmt: &BalanceFormatter,
+    d_matches: &ArgMatches,
+) -> Result<(), CliError> {
+    let from = d.resolve_block(d_matches.value_of("from").ok_or_else(missing_arg)?)?;
+    let to = match d_matches.value_of("to") {
+        Some(to) => d.resolve_block(to)?,
+        None => d.finalized_head()?.0,
+    };
+    let records = ChangeRecord::from_diff(&d.staking_diff(from, to)?, fmt);
+    if print_structured(format, &records)? {
+        return Ok(());
+    }
+    if records.is_empty() {
+        println!("No changes");
+        return Ok(());
+    }
+    let mut t = Table::new();
+    table_header(&mut t, vec!["Change", "Stash", "From", "To"], 160);
+    for r in records {
+        add_row(
+            &mut t,
+            vec![
+                (r.change, Color::Green),
+                (r.stash, Color::Blue),
+                (r.from.replace(';', "\n"), Color::Red),
+                (r.to.replace(';', "\n"), Color::Yellow),
+            ],
+        );
+    }
+    println!("{}", t);
+    Ok(())
+}
+
+fn snapshot(d: &Filling, s_matches: &ArgMatches) -> Result<(), CliError> {
+    match s_matches.subcommand() {
+        ("save", Some(save_matches)) => {
+            let file = save_matches.value_of("file").unwrap();
+            let state = d.snapshot(block_at(d, save_matches)?)?;
+            state.save(Path::new(file))?;
+            println!(
+                "Saved {} entries of block #{} {} to {}",
+                state.entries.len(),
+                state.header.number,
+                storage::to_hex(state.hash().as_bytes()),
+                file
+            );
+            Ok(())
+        }
+        _ => Err(missing_arg()),
+    }
+}
+
+/// Builds ApiFilling for the profile, defaulting to a local polkadot node
+///
+/// Profiles with a snapshot replay it instead of connecting, offline subcommands get no state.
+fn connect(profile: &Profile, offline: bool) -> Result<Filling, DumplingError> {
+    let chain = Chain::from_str(profile.chain.as_deref().unwrap_or("polkadot"))?;
+    let source: Box<dyn StorageSource> = match &profile.snapshot {
+        _ if offline => Box::new(MemorySource::empty()),
+        Some(file) => Box::new(MemorySource::load(Path::new(file))?),
+        None => Box::new(NodeSource::new(
+            profile.endpoint.as_deref().unwrap_or(DEFAULT_URL),
+        )?),
+    };
+    let mut d = ApiFilling::with_source(source, chain);
+    if let Some(prefix) = profile.ss58_format {
+        set_default_ss58_version(Chain::Custom(prefix).ss58_format());
+    }
+    if let Some(decimals) = profile.decimals {
+        d.token.decimals = decimals;
+    }
+    if let Some(symbol) = &profile.symbol {
+        d.token.symbol = symbol.clone();
+    }
+    if let Some(batch_size) = profile.batch_size {
+        d.batch_size = batch_size;
+    }
+    if let Some(page_size) = profile.page_size {
+        d.page_size = page_size;
+    }
+    Ok(d)
+}
+
+/// Whether the one-shot subcommand runs without a node, e.g. on an air-gapped machine
+fn offline(matches: &ArgMatches) -> bool {
+    match matches.subcommand() {
+        ("keys", _) => true,
+        ("tx", Some(t_matches)) => t_matches.subcommand_name() == Some("sign"),
+        _ => false,
+    }
+}
+
+/// Command-line flag if given, otherwise the profile's setting
+fn arg_or_profile(arg: Option<&str>, setting: &Option<String>) -> Option<String> {
+    arg.map(String::from).or_else(|| setting.clone())
+}
+
+/// Command-line flag if given, otherwise the environment variable
+fn arg_or_env(arg: Option<&str>, var: &str) -> Option<String> {
+    arg.map(String::from).or_else(|| env::var(var).ok())
+}
+
 fn table_header(t: &mut Table, headers: Vec<&str>, width: u16) {
     t.set_content_arrangement(ContentArrangement::Dynamic)
         .set_table_width(width);
@@ -258,52 +1582,72 @@ fn add_row(t: &mut Table, rows: Vec<(String, Color)>) {
     t.add_row(t_row);
 }
 
-fn display_nominators(t: &mut Table, m: &HashMap<String, Option<Nominator>>, i: &str) {
+/// Nominator with the given stash, `None` if it has no nominations
+fn nominator_record(
+    m: &HashMap<String, Option<Nominator>>,
+    i: &str,
+    fmt: &BalanceFormatter,
+) -> Result<Option<NominatorRecord>, CliError> {
     match m.get(i) {
-        Some(nominator) => {
-            if let Some(n) = nominator {
-                let mut targets: Vec<String> = Vec::new();
-                for a in &(n.nominations).targets {
-                    targets.push(a.to_ss58check());
-                }
-                add_row(
-                    t,
-                    vec![
-                        (i.to_string(), Color::Blue),
-                        (format!("{:#?}", n.staked), Color::Yellow),
-                        (format!("{:?}", targets), Color::Red),
-                        (format!("{}", n.nominations.submitted_in), Color::Yellow),
-                        (format!("{:#?}", n.nominations.suppressed), Color::Magenta),
-                    ],
-                );
-            }
-        }
+        Some(nominator) => Ok(nominator.as_ref().map(|n| NominatorRecord::new(i, n, fmt))),
         None => {
-            println!("{} is not on current nominators list", i);
+            parse_account(i)?;
+            Err(CliError::NotFound(format!(
+                "{} is not on current nominators list",
+                i
+            )))
         }
     }
 }
 
-fn display_validators(t: &mut Table, m: &HashMap<String, WaitingValidator>, i: &str) {
+fn waiting_validator_record(
+    m: &HashMap<String, WaitingValidator>,
+    i: &str,
+    fmt: &BalanceFormatter,
+) -> Result<WaitingValidatorRecord, CliError> {
     match m.get(i) {
-        Some(a) => {
-            let mut row = vec![
-                (i.to_string(), Color::Blue),
-                (a.staked.to_string(), Color::Green),
-                ("---".to_string(), Color::Yellow),
-                (format!("{:?}", a.nominators), Color::Yellow),
-                (
-                    format!("{:?}", a.prefs.commission),
-                    Color::Magenta,
-                ),
-            ];
-            if let Some(l) = &a.ledger {
-                let n = [(format!("{:#?}", l.claimed_rewards), Color::Green)];
-                row.splice(2..3, n.iter().cloned());
-            }
-
-            add_row(t, row);
+        Some(v) => Ok(WaitingValidatorRecord::new(i, v, fmt)),
+        None => {
+            parse_account(i)?;
+            Err(CliError::NotFound(format!(
+                "{} is not on the waiting validators list",
+                i
+            )))
         }
-        None => println!("{} is not on the waiting validators list", i),
     }
 }
+
+fn display_nominator(t: &mut Table, n: NominatorRecord) {
+    add_row(
+        t,
+        vec![
+            (n.stash, Color::Blue),
+            (n.staked, Color::Yellow),
+            (n.targets.join("\n"), Color::Red),
+            (format!("{}", n.submitted_in), Color::Yellow),
+            (format!("{}", n.suppressed), Color::Magenta),
+        ],
+    );
+}
+
+fn display_validator(t: &mut Table, v: WaitingValidatorRecord) {
+    let claimed = match v.claimed_rewards {
+        Some(c) => (format!("{:?}", c), Color::Green),
+        None => ("---".to_string(), Color::Yellow),
+    };
+    let nominators = if v.nominators.is_empty() {
+        String::from("None")
+    } else {
+        v.nominators.join("\n")
+    };
+    add_row(
+        t,
+        vec![
+            (v.stash, Color::Blue),
+            (v.staked, Color::Green),
+            claimed,
+            (nominators, Color::Yellow),
+            (format!("{}%", v.commission_percent), Color::Magenta),
+        ],
+    );
+}
@@ -0,0 +1,422 @@
+//! Signed extrinsics of staking calls, submitted with ApiFilling::submit_and_track
+
+use crate::{
+    signer::Signer, storage, AccountId, ApiFilling, Balance, DumplingError, Hash, MultiSignature,
+    Pair, Result, StorageKey, StorageSource,
+};
+use codec::{Compact, Encode};
+use sp_runtime::Perbill;
+pub use staking::MAX_NOMINATIONS;
+use staking::{EraIndex, RewardDestination, StakingLedger, UnlockChunk, ValidatorPrefs};
+use substrate_api_client::{
+    compose_call, compose_extrinsic, extrinsic::xt_primitives::GenericAddress, Api,
+};
+
+/// Eras before unbonded funds can be withdrawn, `Staking::BondingDuration` of
+/// the polkadot, kusama and westend runtimes
+pub const BONDING_DURATION: EraIndex = 28;
+
+/// Nominators of a validator paid out each era, `Staking::MaxNominatorRewardedPerValidator` of
+/// the polkadot, kusama and westend runtimes
+pub const MAX_NOMINATOR_REWARDED_PER_VALIDATOR: usize = 64;
+
+/// Staking calls managing a stash's bond and role
+#[derive(Clone, Debug)]
+pub enum StakingCall {
+    /// Signed by the stash
+    Bond {
+        controller: AccountId,
+        value: Balance,
+        payee: RewardDestination,
+    },
+    /// Signed by the stash
+    BondExtra { value: Balance },
+    /// Signed by the controller
+    Unbond { value: Balance },
+    /// Signed by the controller
+    Rebond { value: Balance },
+    /// Signed by the controller
+    WithdrawUnbonded,
+    /// Signed by the controller
+    Nominate { targets: Vec<AccountId> },
+    /// Signed by the controller
+    Validate { commission: Perbill },
+    /// Signed by the controller
+    Chill,
+}
+
+/// Problems found with nomination targets before submitting `nominate`
+#[derive(Debug, Default)]
+pub struct NominationCheck {
+    /// Targets that are not validator candidates
+    pub unknown: Vec<AccountId>,
+    /// More than `MAX_NOMINATIONS` targets
+    pub too_many: bool,
+    /// Queued targets already backed by more nominators than are paid out, with their count
+    pub oversubscribed: Vec<(AccountId, usize)>,
+}
+
+impl NominationCheck {
+    /// Whether the call can succeed, oversubscribed targets only cost rewards
+    pub fn passed(&self) -> bool {
+        self.unknown.is_empty() && !self.too_many
+    }
+}
+
+/// Evaluates `$body` with `$api`, the node api signing as `$signer`
+macro_rules! with_signer {
+    ($filling:expr, $signer:expr, $api:ident => $body:expr) => {
+        match $signer {
+            Signer::Sr25519(pair) => {
+                let $api = $filling.signing_api(pair.clone())?;
+                $body
+            }
+            Signer::Ed25519(pair) => {
+                let $api = $filling.signing_api(pair.clone())?;
+                $body
+            }
+        }
+    };
+}
+
+/// `0x` prefixed signed `Staking` extrinsic
+macro_rules! staking_xt {
+    ($api:expr, $call:expr $(, $args:expr)*) => {
+        compose_extrinsic!($api.clone(), "Staking", $call $(, $args)*).hex_encode()
+    };
+}
+
+/// Encoded `Staking` call
+macro_rules! staking_call {
+    ($metadata:expr, $call:expr $(, $args:expr)*) => {
+        compose_call!($metadata.clone(), "Staking", $call $(, $args)*).encode()
+    };
+}
+
+/// Expands `$compose!($with, call name, args...)` for the StakingCall `$call`
+macro_rules! match_staking_call {
+    ($call:expr, $compose:ident, $with:expr) => {
+        match $call {
+            StakingCall::Bond {
+                controller,
+                value,
+                payee,
+            } => $compose!(
+                $with,
+                "bond",
+                lookup(controller),
+                Compact(*value),
+                payee.clone()
+            ),
+            StakingCall::BondExtra { value } => $compose!($with, "bond_extra", Compact(*value)),
+            StakingCall::Unbond { value } => $compose!($with, "unbond", Compact(*value)),
+            StakingCall::Rebond { value } => $compose!($with, "rebond", Compact(*value)),
+            StakingCall::WithdrawUnbonded => $compose!($with, "withdraw_unbonded"),
+            StakingCall::Nominate { targets } => $compose!(
+                $with,
+                "nominate",
+                targets.iter().map(lookup).collect::<Vec<GenericAddress>>()
+            ),
+            StakingCall::Validate { commission } => $compose!(
+                $with,
+                "validate",
+                ValidatorPrefs {
+                    commission: *commission
+                }
+            ),
+            StakingCall::Chill => $compose!($with, "chill"),
+        }
+    };
+}
+
+impl StakingCall {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StakingCall::Bond { .. } => "bond",
+            StakingCall::BondExtra { .. } => "bond_extra",
+            StakingCall::Unbond { .. } => "unbond",
+            StakingCall::Rebond { .. } => "rebond",
+            StakingCall::WithdrawUnbonded => "withdraw_unbonded",
+            StakingCall::Nominate { .. } => "nominate",
+            StakingCall::Validate { .. } => "validate",
+            StakingCall::Chill => "chill",
+        }
+    }
+
+    /// Whether the controller signs the call, otherwise the stash does
+    pub fn signed_by_controller(&self) -> bool {
+        match self {
+            StakingCall::Bond { .. } | StakingCall::BondExtra { .. } => false,
+            _ => true,
+        }
+    }
+
+    /// Whether the call changes the bonded amounts of the ledger
+    pub fn changes_ledger(&self) -> bool {
+        match self {
+            StakingCall::Nominate { .. } | StakingCall::Validate { .. } | StakingCall::Chill => {
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Ledger after the call, as staking would update it
+    ///
+    /// `bond_extra` cannot bond more than the free balance, the prediction assumes it can.
+    pub fn predict(
+        &self,
+        signer: &AccountId,
+        ledger: Option<&StakingLedger<AccountId, Balance>>,
+        current_era: EraIndex,
+    ) -> Option<StakingLedger<AccountId, Balance>> {
+        let mut ledger = match (self, ledger) {
+            (StakingCall::Bond { value, .. }, None) => {
+                return Some(StakingLedger {
+                    stash: signer.clone(),
+                    total: *value,
+                    active: *value,
+                    unlocking: vec![],
+                    claimed_rewards: vec![],
+                })
+            }
+            (_, None) => return None,
+            (_, Some(ledger)) => ledger.clone(),
+        };
+        match self {
+            // Already bonded the call fails, other calls leave the ledger as it is
+            StakingCall::Bond { .. }
+            | StakingCall::Nominate { .. }
+            | StakingCall::Validate { .. }
+            | StakingCall::Chill => {}
+            StakingCall::BondExtra { value } => {
+                ledger.total += value;
+                ledger.active += value;
+            }
+            StakingCall::Unbond { value } => {
+                let value = (*value).min(ledger.active);
+                if value > 0 {
+                    ledger.active -= value;
+                    ledger.unlocking.push(UnlockChunk {
+                        value,
+                        era: current_era + BONDING_DURATION,
+                    });
+                }
+            }
+            StakingCall::Rebond { value } => {
+                // Latest chunks are rebonded first
+                let mut remaining = *value;
+                while remaining > 0 {
+                    let last = match ledger.unlocking.last_mut() {
+                        Some(last) => last,
+                        None => break,
+                    };
+                    let rebonded = last.value.min(remaining);
+                    last.value -= rebonded;
+                    ledger.active += rebonded;
+                    remaining -= rebonded;
+                    if last.value == 0 {
+                        ledger.unlocking.pop();
+                    }
+                }
+            }
+            StakingCall::WithdrawUnbonded => {
+                let mut withdrawn = 0;
+                ledger.unlocking.retain(|chunk| {
+                    if chunk.era > current_era {
+                        true
+                    } else {
+                        withdrawn += chunk.value;
+                        false
+                    }
+                });
+                ledger.total -= withdrawn;
+                // The stash is removed once nothing is left
+                if ledger.unlocking.is_empty() && ledger.active == 0 {
+                    return None;
+                }
+            }
+        }
+        Some(ledger)
+    }
+}
+
+impl<S: StorageSource> ApiFilling<S> {
+    /// Api of the node, signing with `signer`
+    pub(crate) fn signing_api<P>(&self, signer: P) -> Result<Api<P>>
+    where
+        P: Pair,
+        MultiSignature: From<P::Signature>,
+    {
+        let node = self.source.node().ok_or_else(|| {
+            DumplingError::Unsupported("extrinsics without a node connection".to_string())
+        })?;
+        // Same node, metadata and runtime, with a signer of another crypto
+        Ok(Api {
+            url: node.api.url.clone(),
+            signer: Some(signer),
+            genesis_hash: node.api.genesis_hash,
+            metadata: node.api.metadata.clone(),
+            runtime_version: node.api.runtime_version.clone(),
+        })
+    }
+
+    /// Ledger the call changes, found through the stash or the controller signing it
+    pub fn ledger_for(
+        &self,
+        call: &StakingCall,
+        signer: &AccountId,
+        block_hash: Option<Hash>,
+    ) -> Result<Option<StakingLedger<AccountId, Balance>>> {
+        if call.signed_by_controller() {
+            Ok(self
+                .decode_batch(&[storage::ledger_key(signer)], block_hash)?
+                .pop()
+                .flatten())
+        } else {
+            Ok(self.ledgers(&[signer.clone()], block_hash)?.pop().flatten())
+        }
+    }
+
+    /// `0x` prefixed extrinsic signing the call, it can then be submitted or its fee estimated
+    pub fn sign_staking_call(&self, signer: &Signer, call: &StakingCall) -> Result<String> {
+        Ok(with_signer!(self, signer, api => staking_extrinsic(&api, call)))
+    }
+
+    /// SCALE encoded call, with the call indices of the node's metadata
+    pub fn encode_staking_call(&self, call: &StakingCall) -> Result<Vec<u8>> {
+        let node = self.source.node().ok_or_else(|| {
+            DumplingError::Unsupported("call encoding without a node connection".to_string())
+        })?;
+        Ok(match_staking_call!(call, staking_call, node.api.metadata))
+    }
+
+    /// SCALE encoded `payout_stakers`, batched when there are several payouts
+    pub fn encode_payout_call(&self, payouts: &[(AccountId, EraIndex)]) -> Result<Vec<u8>> {
+        let node = self.source.node().ok_or_else(|| {
+            DumplingError::Unsupported("call encoding without a node connection".to_string())
+        })?;
+        let metadata = &node.api.metadata;
+        Ok(match payouts {
+            [] => return Err(DumplingError::Extrinsic("no payout to submit".to_string())),
+            [(stash, era)] => compose_call!(
+                metadata.clone(),
+                "Staking",
+                "payout_stakers",
+                stash.clone(),
+                *era
+            )
+            .encode(),
+            _ => {
+                let calls: Vec<_> = payouts
+                    .iter()
+                    .map(|(stash, era)| {
+                        compose_call!(
+                            metadata.clone(),
+                            "Staking",
+                            "payout_stakers",
+                            stash.clone(),
+                            *era
+                        )
+                    })
+                    .collect();
+                compose_call!(metadata.clone(), "Utility", "batch", calls).encode()
+            }
+        })
+    }
+
+    /// Checks `nominate` targets against the validator candidates and the queued election
+    pub fn check_nominations(
+        &self,
+        targets: &[AccountId],
+        block_hash: Option<Hash>,
+    ) -> Result<NominationCheck> {
+        let block_hash = Some(self.pin(block_hash)?);
+        let keys: Vec<StorageKey> = targets.iter().map(storage::validators_key).collect();
+        let prefs = self.decode_batch::<ValidatorPrefs>(&keys, block_hash)?;
+        let queued = self.queued_validators(block_hash)?;
+        let mut check = NominationCheck {
+            too_many: targets.len() > MAX_NOMINATIONS,
+            ..Default::default()
+        };
+        for (target, prefs) in targets.iter().zip(prefs) {
+            if prefs.is_none() {
+                check.unknown.push(target.clone());
+            }
+            let exposure = queued
+                .iter()
+                .flat_map(|q| q.exposures.iter())
+                .find(|(stash, _)| stash == target);
+            if let Some((_, exposure)) = exposure {
+                if exposure.others.len() > MAX_NOMINATOR_REWARDED_PER_VALIDATOR {
+                    check
+                        .oversubscribed
+                        .push((target.clone(), exposure.others.len()));
+                }
+            }
+        }
+        Ok(check)
+    }
+
+    /// Signed `payout_stakers` of `(validator stash, era)` pairs, submitted with
+    /// submit_and_track
+    ///
+    /// Several payouts are sent as one `Utility::batch`.
+    pub fn sign_payout_stakers(
+        &self,
+        signer: &Signer,
+        payouts: &[(AccountId, EraIndex)],
+    ) -> Result<String> {
+        if payouts.is_empty() {
+            return Err(DumplingError::Extrinsic("no payout to submit".to_string()));
+        }
+        Ok(with_signer!(self, signer, api => payout_extrinsic(&api, payouts)))
+    }
+}
+
+/// Signed `payout_stakers`, batched when there are several payouts
+fn payout_extrinsic<P>(api: &Api<P>, payouts: &[(AccountId, EraIndex)]) -> String
+where
+    P: Pair,
+    MultiSignature: From<P::Signature>,
+{
+    match payouts {
+        [(stash, era)] => compose_extrinsic!(
+            api.clone(),
+            "Staking",
+            "payout_stakers",
+            stash.clone(),
+            *era
+        )
+        .hex_encode(),
+        _ => {
+            let calls: Vec<_> = payouts
+                .iter()
+                .map(|(stash, era)| {
+                    compose_call!(
+                        api.metadata.clone(),
+                        "Staking",
+                        "payout_stakers",
+                        stash.clone(),
+                        *era
+                    )
+                })
+                .collect();
+            compose_extrinsic!(api.clone(), "Utility", "batch", calls).hex_encode()
+        }
+    }
+}
+
+/// Signed `call`
+fn staking_extrinsic<P>(api: &Api<P>, call: &StakingCall) -> String
+where
+    P: Pair,
+    MultiSignature: From<P::Signature>,
+{
+    match_staking_call!(call, staking_xt, api)
+}
+
+/// Account as the `Lookup` source of the runtime
+pub(crate) fn lookup(account: &AccountId) -> GenericAddress {
+    GenericAddress::from(account.clone())
+}
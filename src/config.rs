@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Contents of `~/.config/dumpling/config.toml`
+///
+/// ```toml
+/// default_profile = "kusama"
+///
+/// [profiles.kusama]
+/// endpoint = "wss://kusama-rpc.polkadot.io"
+/// chain = "kusama"
+/// output = "table"
+/// decimals = 12
+/// watched = ["HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F"]
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Config {
+    /// Profile used on start up when none is given
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// A named network profile, every field is optional
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Profile {
+    /// Node websocket endpoint
+    pub endpoint: Option<String>,
+    /// polkadot, kusama, westend or a custom ss58 prefix
+    pub chain: Option<String>,
+    /// ss58 prefix used to display addresses, overrides the chain's own
+    pub ss58_format: Option<u8>,
+    /// Default output format
+    pub output: Option<String>,
+    /// Token decimals, overrides the node's
+    pub decimals: Option<u8>,
+    /// Token symbol, overrides the node's
+    pub symbol: Option<String>,
+    /// Storage keys fetched per request
+    pub batch_size: Option<usize>,
+    /// Keys per page when listing storage maps
+    pub page_size: Option<u32>,
+    /// Snapshot file replayed instead of querying the node
+    pub snapshot: Option<String>,
+    /// File with the secret uri of the key signing extrinsics
+    pub seed_file: Option<String>,
+    /// Name or account of the keystore key signing extrinsics, preferred over `seed_file`
+    pub key: Option<String>,
+    /// Keystore directory, see Keystore::default_dir
+    pub keystore: Option<String>,
+    /// Accounts of interest, e.g. our own stashes
+    #[serde(default)]
+    pub watched: Vec<String>,
+    /// `daemon` alerts when fewer nominators back a watched stash
+    pub min_nominators: Option<usize>,
+    /// Local http url `daemon` POSTs alerts to
+    pub alert_webhook: Option<String>,
+    /// Shell command `daemon` runs for every alert
+    pub alert_exec: Option<String>,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/dumpling/config.toml`, or `~/.config/dumpling/config.toml`
+    pub fn default_path() -> Option<PathBuf> {
+        let base = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => dirs::home_dir()?.join(".config"),
+        };
+        Some(base.join("dumpling").join("config.toml"))
+    }
+
+    /// Reads the config at `path`, a missing file is an empty config
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        toml::from_str(&content).map_err(|e| format!("Invalid {}: {}", path.display(), e))
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles.get(name)
+    }
+}
@@ -0,0 +1,124 @@
+//! Changes of the staking state between two blocks
+
+use crate::{AccountId, Balance, Nominator, WaitingValidator};
+use sp_runtime::Perbill;
+use std::collections::HashMap;
+
+/// What changed in the waiting validators and nominators, every list sorted by stash
+#[derive(Debug, Default)]
+pub struct StakingDiff {
+    pub validators_entered: Vec<String>,
+    pub validators_left: Vec<String>,
+    pub nominators_added: Vec<String>,
+    pub nominators_removed: Vec<String>,
+    pub targets_changed: Vec<TargetsChange>,
+    /// Stake changes of validators and nominators present at both blocks
+    pub stake_changes: Vec<StakeChange>,
+    pub commission_changes: Vec<CommissionChange>,
+}
+
+#[derive(Debug)]
+pub struct TargetsChange {
+    pub stash: String,
+    pub added: Vec<AccountId>,
+    pub removed: Vec<AccountId>,
+}
+
+#[derive(Debug)]
+pub struct StakeChange {
+    pub stash: String,
+    pub from: Balance,
+    pub to: Balance,
+}
+
+#[derive(Debug)]
+pub struct CommissionChange {
+    pub stash: String,
+    pub from: Perbill,
+    pub to: Perbill,
+}
+
+impl StakingDiff {
+    pub fn is_empty(&self) -> bool {
+        self.validators_entered.is_empty()
+            && self.validators_left.is_empty()
+            && self.nominators_added.is_empty()
+            && self.nominators_removed.is_empty()
+            && self.targets_changed.is_empty()
+            && self.stake_changes.is_empty()
+            && self.commission_changes.is_empty()
+    }
+}
+
+/// Compares the waiting validators and nominators read at two blocks
+pub fn diff(
+    validators_before: &HashMap<String, WaitingValidator>,
+    nominators_before: &HashMap<String, Option<Nominator>>,
+    validators_after: &HashMap<String, WaitingValidator>,
+    nominators_after: &HashMap<String, Option<Nominator>>,
+) -> StakingDiff {
+    let mut d = StakingDiff {
+        validators_entered: missing_from(validators_after, validators_before),
+        validators_left: missing_from(validators_before, validators_after),
+        nominators_added: missing_from(nominators_after, nominators_before),
+        nominators_removed: missing_from(nominators_before, nominators_after),
+        ..Default::default()
+    };
+
+    for (stash, before) in sorted(validators_before) {
+        if let Some(after) = validators_after.get(stash) {
+            if before.staked != after.staked {
+                d.stake_changes.push(StakeChange {
+                    stash: stash.clone(),
+                    from: before.staked,
+                    to: after.staked,
+                });
+            }
+            if before.prefs.commission != after.prefs.commission {
+                d.commission_changes.push(CommissionChange {
+                    stash: stash.clone(),
+                    from: before.prefs.commission,
+                    to: after.prefs.commission,
+                });
+            }
+        }
+    }
+
+    for (stash, before) in sorted(nominators_before) {
+        if let (Some(before), Some(Some(after))) = (before, nominators_after.get(stash)) {
+            if before.staked != after.staked {
+                d.stake_changes.push(StakeChange {
+                    stash: stash.clone(),
+                    from: before.staked,
+                    to: after.staked,
+                });
+            }
+            let old = &before.nominations.targets;
+            let new = &after.nominations.targets;
+            let added: Vec<AccountId> = new.iter().filter(|t| !old.contains(t)).cloned().collect();
+            let removed: Vec<AccountId> =
+                old.iter().filter(|t| !new.contains(t)).cloned().collect();
+            if !added.is_empty() || !removed.is_empty() {
+                d.targets_changed.push(TargetsChange {
+                    stash: stash.clone(),
+                    added,
+                    removed,
+                });
+            }
+        }
+    }
+    d
+}
+
+fn sorted<V>(m: &HashMap<String, V>) -> Vec<(&String, &V)> {
+    let mut entries: Vec<(&String, &V)> = m.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
+/// Stashes of `a` that are not in `b`
+fn missing_from<V, W>(a: &HashMap<String, V>, b: &HashMap<String, W>) -> Vec<String> {
+    let mut stashes: Vec<String> = a.keys().filter(|k| !b.contains_key(*k)).cloned().collect();
+    stashes.sort();
+    stashes
+}
@@ -0,0 +1,47 @@
+use std::fmt;
+
+/// Errors returned by ApiFilling
+#[derive(Debug)]
+pub enum DumplingError {
+    /// Node could not be reached or did not answer an rpc call
+    Connection(String),
+    /// Storage key or value could not be decoded
+    Decode(String),
+    /// Data could not be written in the requested output format
+    Encode(String),
+    /// Storage entry is not present at the queried block
+    MissingStorage(String),
+    /// Not a valid ss58 address
+    InvalidAddress(String),
+    /// Key or secret could not be read
+    InvalidKey(String),
+    /// Extrinsic was not included in a block
+    Extrinsic(String),
+    /// Chain name or ss58 prefix not supported
+    UnsupportedChain(String),
+    /// File could not be read or written
+    Io(String),
+    /// Operation the storage source cannot perform
+    Unsupported(String),
+}
+
+impl fmt::Display for DumplingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DumplingError::Connection(e) => write!(f, "Connection error: {}", e),
+            DumplingError::Decode(e) => write!(f, "Cannot decode {}", e),
+            DumplingError::Encode(e) => write!(f, "Cannot encode output: {}", e),
+            DumplingError::MissingStorage(e) => write!(f, "{} is not available", e),
+            DumplingError::InvalidAddress(e) => write!(f, "{} is not a valid address", e),
+            DumplingError::InvalidKey(e) => write!(f, "Invalid key: {}", e),
+            DumplingError::Extrinsic(e) => write!(f, "Extrinsic failed: {}", e),
+            DumplingError::UnsupportedChain(e) => write!(f, "Chain not supported: {}", e),
+            DumplingError::Io(e) => write!(f, "Cannot access {}", e),
+            DumplingError::Unsupported(e) => write!(f, "Not supported: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DumplingError {}
+
+pub type Result<T> = std::result::Result<T, DumplingError>;
@@ -0,0 +1,154 @@
+//! Events of a submitted extrinsic, decoded with the node's metadata
+//!
+//! Only the events of a few staking calls are decoded further, the others are reported by name.
+
+use crate::{storage, AccountId, ApiFilling, Balance, DumplingError, Hash, Result, StorageSource};
+use codec::Decode;
+use serde_json::{json, Value};
+use sp_runtime::DispatchError;
+use std::convert::TryFrom;
+use substrate_api_client::{
+    events::{EventsDecoder, Phase, RuntimeEvent, SystemEvent},
+    node_metadata::Metadata,
+};
+
+/// An event emitted while applying an extrinsic
+#[derive(Clone, Debug, PartialEq)]
+pub enum TxEvent {
+    /// `System::ExtrinsicSuccess`
+    Success,
+    /// `System::ExtrinsicFailed`, with the `Module::Error` name or the dispatch error
+    Failed(String),
+    /// `Staking::Bonded` of a stash, by `bond`, `bond_extra` or `rebond`
+    Bonded(AccountId, Balance),
+    /// `Staking::Reward` of a stash, by `payout_stakers`
+    Reward(AccountId, Balance),
+    /// Any other event, by module and name
+    Other(String, String),
+}
+
+impl TxEvent {
+    /// Event `variant` of `module` with its SCALE encoded arguments
+    pub fn from_raw(module: &str, variant: &str, data: &[u8]) -> Result<Self> {
+        let decode = || {
+            <(AccountId, Balance)>::decode(&mut &data[..])
+                .map_err(|_| DumplingError::Decode(format!("{}::{} event", module, variant)))
+        };
+        Ok(match (module, variant) {
+            ("Staking", "Bonded") => {
+                let (stash, value) = decode()?;
+                TxEvent::Bonded(stash, value)
+            }
+            ("Staking", "Reward") => {
+                let (stash, value) = decode()?;
+                TxEvent::Reward(stash, value)
+            }
+            _ => TxEvent::Other(module.to_string(), variant.to_string()),
+        })
+    }
+
+    /// A `System` event, the error of `ExtrinsicFailed` as it is without the metadata
+    pub fn from_system(event: &SystemEvent) -> Self {
+        let other = |variant: &str| TxEvent::Other("System".to_string(), variant.to_string());
+        match event {
+            SystemEvent::ExtrinsicSuccess(_) => TxEvent::Success,
+            SystemEvent::ExtrinsicFailed(error, _) => TxEvent::Failed(format!("{:?}", error)),
+            SystemEvent::CodeUpdated => other("CodeUpdated"),
+            SystemEvent::NewAccount(_) => other("NewAccount"),
+            SystemEvent::KilledAccount(_) => other("KilledAccount"),
+        }
+    }
+}
+
+/// What an extrinsic did, from the `System::Events` of the block including it
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxOutcome {
+    /// Index of the extrinsic in its block
+    pub index: u32,
+    pub events: Vec<TxEvent>,
+}
+
+impl TxOutcome {
+    /// Why the extrinsic failed, None if it succeeded
+    pub fn error(&self) -> Option<&str> {
+        self.events.iter().find_map(|event| match event {
+            TxEvent::Failed(error) => Some(error.as_str()),
+            _ => None,
+        })
+    }
+}
+
+impl<S: StorageSource> ApiFilling<S> {
+    /// Events of the signed `extrinsic` included in `block`
+    pub fn extrinsic_outcome(&self, extrinsic: &str, block: Hash) -> Result<TxOutcome> {
+        let node = self.source.node().ok_or_else(|| {
+            DumplingError::Unsupported("events without a node connection".to_string())
+        })?;
+        let index = self.extrinsic_index(extrinsic, block)?;
+        let raw = self
+            .source
+            .storage(&storage::value_key("System", "Events"), Some(block))?
+            .unwrap_or_default();
+
+        let metadata = node.api.metadata.clone();
+        let decoder = EventsDecoder::try_from(metadata.clone())
+            .map_err(|e| DumplingError::Decode(format!("event metadata: {:?}", e)))?;
+        let events = decoder
+            .decode_events(&mut &raw[..])
+            .map_err(|e| DumplingError::Decode(format!("events: {:?}", e)))?;
+
+        let mut outcome = TxOutcome {
+            index,
+            events: Vec::new(),
+        };
+        for (phase, event) in events {
+            match phase {
+                Phase::ApplyExtrinsic(i) if i == index => {}
+                _ => continue,
+            }
+            outcome.events.push(match event {
+                RuntimeEvent::System(SystemEvent::ExtrinsicFailed(error, _)) => {
+                    TxEvent::Failed(dispatch_error(&metadata, &error))
+                }
+                RuntimeEvent::System(event) => TxEvent::from_system(&event),
+                RuntimeEvent::Raw(raw) => TxEvent::from_raw(&raw.module, &raw.variant, &raw.data)?,
+            });
+        }
+        Ok(outcome)
+    }
+
+    /// Position of `extrinsic` in the body of `block`
+    fn extrinsic_index(&self, extrinsic: &str, block: Hash) -> Result<u32> {
+        let response = self.rpc("chain_getBlock", json!([storage::at_param(Some(block))]))?;
+        response["block"]["extrinsics"]
+            .as_array()
+            .and_then(|extrinsics| {
+                extrinsics.iter().position(|xt| match xt {
+                    Value::String(xt) => xt.eq_ignore_ascii_case(extrinsic),
+                    _ => false,
+                })
+            })
+            .map(|i| i as u32)
+            .ok_or_else(|| {
+                DumplingError::MissingStorage(format!(
+                    "extrinsic in block {}",
+                    storage::to_hex(block.as_bytes())
+                ))
+            })
+    }
+}
+
+/// `Module::Error` name of a module error, from the metadata
+fn dispatch_error(metadata: &Metadata, error: &DispatchError) -> String {
+    match error {
+        DispatchError::Module { index, error, .. } => metadata
+            .module_with_errors(*index)
+            .ok()
+            .and_then(|module| {
+                let name = module.error(*error).ok()?;
+                Some(format!("{}::{}", module.name, name))
+            })
+            .unwrap_or_else(|| format!("module {} error {}", index, error)),
+        _ => format!("{:?}", error),
+    }
+}
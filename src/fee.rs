@@ -0,0 +1,69 @@
+//! Fees of extrinsics, as the node computes them for `payment_queryInfo`
+
+use crate::{
+    signer::{CryptoType, Signer},
+    storage,
+    tx::UnsignedTx,
+    ApiFilling, Balance, DumplingError, Hash, Result, Ss58Codec, StorageSource,
+};
+use serde_json::{json, Value};
+
+/// Key signing extrinsics whose fee is estimated before they are signed
+///
+/// The fee only depends on the call and the length of the extrinsic, not on who signs it.
+const FEE_ESTIMATE_KEY: &str = "//DumplingFeeEstimate";
+
+/// Dispatch info and fee of an extrinsic
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeeInfo {
+    pub weight: u64,
+    /// `Normal`, `Operational` or `Mandatory`
+    pub class: String,
+    /// Fee without tip, taken from the free balance of the signer
+    pub partial_fee: Balance,
+}
+
+impl FeeInfo {
+    /// Reads a `payment_queryInfo` result, fees too large for json numbers are strings
+    pub fn from_response(info: &Value) -> Option<Self> {
+        let partial_fee = match info.get("partialFee")? {
+            Value::String(fee) => fee.parse().ok()?,
+            Value::Number(fee) => Balance::from(fee.as_u64()?),
+            _ => return None,
+        };
+        Some(Self {
+            weight: info.get("weight")?.as_u64()?,
+            class: info.get("class")?.as_str()?.to_string(),
+            partial_fee,
+        })
+    }
+}
+
+impl<S: StorageSource> ApiFilling<S> {
+    /// Weight and fee of a `0x` prefixed signed extrinsic
+    pub fn query_fee(&self, extrinsic: &str) -> Result<FeeInfo> {
+        let info = self.rpc("payment_queryInfo", json!([extrinsic]))?;
+        FeeInfo::from_response(&info)
+            .ok_or_else(|| DumplingError::Decode(format!("payment_queryInfo response: {}", info)))
+    }
+
+    /// Weight and fee of an encoded call, signed by a throwaway key
+    ///
+    /// The estimate can be off by the length fee of a couple of bytes, the mortality and nonce
+    /// of the extrinsic finally submitted.
+    pub fn call_fee(&self, call: &[u8]) -> Result<FeeInfo> {
+        let signer = Signer::from_uri(FEE_ESTIMATE_KEY, CryptoType::Sr25519)?;
+        let unsigned = UnsignedTx {
+            signer: signer.account().to_ss58check(),
+            call: storage::to_hex(call),
+            nonce: 0,
+            tip: 0,
+            era: "0x00".to_string(),
+            era_hash: Hash::zero(),
+            genesis_hash: Hash::zero(),
+            spec_version: 0,
+            transaction_version: None,
+        };
+        self.query_fee(&unsigned.sign(&signer)?)
+    }
+}
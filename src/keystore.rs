@@ -0,0 +1,359 @@
+//! Encrypted signing keys, stored as polkadot-js json exports
+//!
+//! Secrets are encrypted with xsalsa20-poly1305 under a scrypt key derived from a password,
+//! so keys move between dumpling and polkadot-js in both directions.
+
+use crate::{
+    ed25519, parse_account,
+    signer::{CryptoType, Signer},
+    sr25519, storage, AccountId, DumplingError, Pair, Result, Ss58Codec,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use xsalsa20poly1305::aead::{generic_array::GenericArray, Aead, NewAead};
+use xsalsa20poly1305::XSalsa20Poly1305;
+
+const PKCS8_HEADER: [u8; 16] = [48, 83, 2, 1, 1, 48, 5, 6, 3, 43, 101, 112, 4, 34, 4, 32];
+const PKCS8_DIVIDER: [u8; 5] = [161, 35, 3, 33, 0];
+const SECRET_LEN: usize = 64;
+const PUBLIC_LEN: usize = 32;
+const SALT_LEN: usize = 32;
+const NONCE_LEN: usize = 24;
+/// scrypt parameters of polkadot-js, N = 2^15
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_P: u32 = 1;
+const SCRYPT_R: u32 = 8;
+
+/// A key as exported by polkadot-js
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct KeyJson {
+    pub address: String,
+    /// Encrypted PKCS8 secret, base64 or `0x` prefixed hex
+    pub encoded: String,
+    pub encoding: Encoding,
+    #[serde(default)]
+    pub meta: Meta,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Encoding {
+    /// `["pkcs8", "sr25519"]` or `["pkcs8", "ed25519"]`
+    pub content: Vec<String>,
+    /// `["scrypt", "xsalsa20-poly1305"]`, older exports only have `"xsalsa20-poly1305"`
+    #[serde(rename = "type", deserialize_with = "one_or_many")]
+    pub kind: Vec<String>,
+    pub version: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Meta {
+    pub name: Option<String>,
+    #[serde(rename = "whenCreated")]
+    pub when_created: Option<u64>,
+}
+
+/// A key of the keystore, still encrypted
+#[derive(Clone, Debug)]
+pub struct KeyEntry {
+    pub name: String,
+    pub account: AccountId,
+    pub crypto: CryptoType,
+    pub path: PathBuf,
+}
+
+/// Directory of encrypted keys, one json file per account
+pub struct Keystore {
+    pub dir: PathBuf,
+}
+
+impl Keystore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// `$XDG_DATA_HOME/dumpling/keystore`, or `~/.local/share/dumpling/keystore`
+    pub fn default_dir() -> Option<PathBuf> {
+        let base = match env::var_os("XDG_DATA_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => dirs::home_dir()?.join(".local").join("share"),
+        };
+        Some(base.join("dumpling").join("keystore"))
+    }
+
+    /// Encrypts and stores `signer` under `name`
+    pub fn add(&self, signer: &Signer, name: &str, password: &str) -> Result<KeyEntry> {
+        self.store(&encrypt(signer, name, password)?)
+    }
+
+    /// Stores a polkadot-js export under `name`, once `password` decrypts it
+    pub fn import_json(&self, json: &str, name: &str, password: &str) -> Result<KeyEntry> {
+        let mut key: KeyJson = serde_json::from_str(json)
+            .map_err(|e| DumplingError::InvalidKey(format!("json export, {}", e)))?;
+        decrypt(&key, password)?;
+        key.meta.name = Some(name.to_string());
+        self.store(&key)
+    }
+
+    /// Every key, sorted by name
+    pub fn list(&self) -> Result<Vec<KeyEntry>> {
+        if !self.dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut entries = Vec::new();
+        for file in fs::read_dir(&self.dir).map_err(|e| io_error(&self.dir, e))? {
+            let path = file.map_err(|e| io_error(&self.dir, e))?.path();
+            if path.extension().map_or(false, |e| e == "json") {
+                entries.push(entry(&read(&path)?, path)?);
+            }
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    /// Key named `key`, or whose account is `key`
+    pub fn find(&self, key: &str) -> Result<KeyEntry> {
+        let account = parse_account(key).ok();
+        self.list()?
+            .into_iter()
+            .find(|e| e.name == key || Some(&e.account) == account.as_ref())
+            .ok_or_else(|| DumplingError::InvalidKey(format!("{} is not in the keystore", key)))
+    }
+
+    /// Decrypts the key named `key`, or whose account is `key`
+    pub fn unlock(&self, key: &str, password: &str) -> Result<Signer> {
+        decrypt(&read(&self.find(key)?.path)?, password)
+    }
+
+    fn store(&self, key: &KeyJson) -> Result<KeyEntry> {
+        let name = key.meta.name.clone().unwrap_or_default();
+        let account = parse_account(&key.address)?;
+        if self
+            .list()?
+            .iter()
+            .any(|e| e.account == account || e.name == name)
+        {
+            return Err(DumplingError::InvalidKey(format!(
+                "{} or {} is already in the keystore",
+                name,
+                account.to_ss58check()
+            )));
+        }
+        fs::create_dir_all(&self.dir).map_err(|e| io_error(&self.dir, e))?;
+        let file = storage::to_hex(AsRef::<[u8]>::as_ref(&account));
+        let path = self.dir.join(format!("{}.json", &file[2..]));
+        let json =
+            serde_json::to_string_pretty(key).map_err(|e| DumplingError::Encode(e.to_string()))?;
+        write_private(&path, json.as_bytes())?;
+        entry(key, path)
+    }
+}
+
+/// Encrypts the secret of `signer` as polkadot-js does
+pub fn encrypt(signer: &Signer, name: &str, password: &str) -> Result<KeyJson> {
+    let (secret, public) = match signer {
+        Signer::Sr25519(pair) => {
+            // schnorrkel secret key and nonce, polkadot-js keeps the key in ed25519 form
+            let mut secret = pair.to_raw_vec();
+            multiply_by_cofactor(&mut secret[..32]);
+            (secret, pair.public().0.to_vec())
+        }
+        Signer::Ed25519(pair) => {
+            let public = pair.public().0.to_vec();
+            ([pair.to_raw_vec(), public.clone()].concat(), public)
+        }
+    };
+    let plain = [&PKCS8_HEADER[..], &secret, &PKCS8_DIVIDER, &public].concat();
+
+    let salt: [u8; SALT_LEN] = rand::random();
+    let nonce: [u8; NONCE_LEN] = rand::random();
+    let key = scrypt_key(password, &salt, SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P)?;
+    let sealed = XSalsa20Poly1305::new(GenericArray::from_slice(&key))
+        .encrypt(GenericArray::from_slice(&nonce), plain.as_ref())
+        .map_err(|_| DumplingError::Encode("key encryption".to_string()))?;
+    let mut encoded = salt.to_vec();
+    for param in &[1u32 << SCRYPT_LOG_N, SCRYPT_P, SCRYPT_R] {
+        encoded.extend_from_slice(&param.to_le_bytes());
+    }
+    encoded.extend_from_slice(&nonce);
+    encoded.extend_from_slice(&sealed);
+
+    Ok(KeyJson {
+        address: signer.account().to_ss58check(),
+        encoded: base64::encode(&encoded),
+        encoding: Encoding {
+            content: vec!["pkcs8".to_string(), signer.crypto().to_string()],
+            kind: vec!["scrypt".to_string(), "xsalsa20-poly1305".to_string()],
+            version: "3".to_string(),
+        },
+        meta: Meta {
+            name: Some(name.to_string()),
+            when_created: Some(chrono::Utc::now().timestamp_millis() as u64),
+        },
+    })
+}
+
+/// Decrypts a polkadot-js export with `password`
+pub fn decrypt(key: &KeyJson, password: &str) -> Result<Signer> {
+    let crypto = crypto_of(key)?;
+    let encoded = if key.encoded.starts_with("0x") {
+        storage::from_hex(&key.encoded).ok()
+    } else {
+        base64::decode(&key.encoded).ok()
+    }
+    .ok_or_else(|| DumplingError::InvalidKey("encoded secret".to_string()))?;
+    let truncated = || DumplingError::InvalidKey("truncated secret".to_string());
+
+    let (cipher_key, sealed) = if key.encoding.kind.iter().any(|t| t == "scrypt") {
+        if encoded.len() < SALT_LEN + 12 {
+            return Err(truncated());
+        }
+        let param = |i: usize| {
+            let at = SALT_LEN + 4 * i;
+            u32::from_le_bytes([
+                encoded[at],
+                encoded[at + 1],
+                encoded[at + 2],
+                encoded[at + 3],
+            ])
+        };
+        let (n, p, r) = (param(0), param(1), param(2));
+        // Parameters above the polkadot-js ones would take gigabytes of memory or hang
+        if !n.is_power_of_two() || n > 1 << SCRYPT_LOG_N || r > SCRYPT_R || p > SCRYPT_P {
+            return Err(DumplingError::InvalidKey(format!(
+                "scrypt N {}, r {}, p {}",
+                n, r, p
+            )));
+        }
+        let log_n = n.trailing_zeros() as u8;
+        let cipher_key = scrypt_key(password, &encoded[..SALT_LEN], log_n, r, p)?;
+        (cipher_key, &encoded[SALT_LEN + 12..])
+    } else {
+        // Older exports use the password itself, zero padded to 32 bytes
+        let mut cipher_key = password.as_bytes().to_vec();
+        cipher_key.resize(32, 0);
+        (cipher_key, &encoded[..])
+    };
+    if sealed.len() < NONCE_LEN {
+        return Err(truncated());
+    }
+    let plain = XSalsa20Poly1305::new(GenericArray::from_slice(&cipher_key[..32]))
+        .decrypt(
+            GenericArray::from_slice(&sealed[..NONCE_LEN]),
+            &sealed[NONCE_LEN..],
+        )
+        .map_err(|_| DumplingError::InvalidKey("wrong password".to_string()))?;
+
+    let secret_at = PKCS8_HEADER.len();
+    let public_at = secret_at + SECRET_LEN + PKCS8_DIVIDER.len();
+    if plain.len() < public_at + PUBLIC_LEN
+        || plain[..secret_at] != PKCS8_HEADER
+        || plain[secret_at + SECRET_LEN..public_at] != PKCS8_DIVIDER
+    {
+        return Err(DumplingError::InvalidKey("pkcs8 content".to_string()));
+    }
+    let mut secret = plain[secret_at..secret_at + SECRET_LEN].to_vec();
+    let invalid = |_| DumplingError::InvalidKey("secret".to_string());
+    let signer = match crypto {
+        CryptoType::Sr25519 => {
+            divide_by_cofactor(&mut secret[..32]);
+            Signer::Sr25519(sr25519::Pair::from_seed_slice(&secret).map_err(invalid)?)
+        }
+        CryptoType::Ed25519 => {
+            Signer::Ed25519(ed25519::Pair::from_seed_slice(&secret[..32]).map_err(invalid)?)
+        }
+    };
+    if AsRef::<[u8]>::as_ref(&signer.account()) != &plain[public_at..public_at + PUBLIC_LEN] {
+        return Err(DumplingError::InvalidKey(
+            "public key does not match the secret".to_string(),
+        ));
+    }
+    Ok(signer)
+}
+
+fn crypto_of(key: &KeyJson) -> Result<CryptoType> {
+    match key.encoding.content.get(1) {
+        Some(crypto) => crypto.parse(),
+        None => Err(DumplingError::InvalidKey("encoding content".to_string())),
+    }
+}
+
+fn entry(key: &KeyJson, path: PathBuf) -> Result<KeyEntry> {
+    Ok(KeyEntry {
+        name: key.meta.name.clone().unwrap_or_default(),
+        account: parse_account(&key.address)?,
+        crypto: crypto_of(key)?,
+        path,
+    })
+}
+
+fn read(path: &Path) -> Result<KeyJson> {
+    let json = fs::read_to_string(path).map_err(|e| io_error(path, e))?;
+    serde_json::from_str(&json)
+        .map_err(|e| DumplingError::InvalidKey(format!("{}, {}", path.display(), e)))
+}
+
+/// Writes a file only its owner can read
+fn write_private(path: &Path, content: &[u8]) -> Result<()> {
+    use std::io::Write;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(content))
+        .map_err(|e| io_error(path, e))
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> DumplingError {
+    DumplingError::Io(format!("{}: {}", path.display(), e))
+}
+
+fn scrypt_key(password: &str, salt: &[u8], log_n: u8, r: u32, p: u32) -> Result<Vec<u8>> {
+    let params = scrypt::ScryptParams::new(log_n, r, p)
+        .map_err(|_| DumplingError::InvalidKey("scrypt parameters".to_string()))?;
+    let mut key = vec![0; 32];
+    scrypt::scrypt(password.as_bytes(), salt, &params, &mut key)
+        .map_err(|_| DumplingError::InvalidKey("scrypt output".to_string()))?;
+    Ok(key)
+}
+
+/// schnorrkel to ed25519 form of a secret scalar, little endian
+fn multiply_by_cofactor(scalar: &mut [u8]) {
+    let mut high = 0;
+    for byte in scalar.iter_mut() {
+        let carry = *byte >> 5;
+        *byte = (*byte << 3) | high;
+        high = carry;
+    }
+}
+
+/// ed25519 to schnorrkel form of a secret scalar, little endian
+fn divide_by_cofactor(scalar: &mut [u8]) {
+    let mut low = 0;
+    for byte in scalar.iter_mut().rev() {
+        let carry = *byte & 0b111;
+        *byte = (*byte >> 3) | low;
+        low = carry << 5;
+    }
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(kind) => vec![kind],
+        OneOrMany::Many(kinds) => kinds,
+    })
+}
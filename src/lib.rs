@@ -1,218 +1,729 @@
+pub mod alert;
+#[cfg(feature = "async")]
+pub mod async_filling;
+#[cfg(feature = "async")]
+pub mod async_source;
+pub mod balance;
+pub mod calls;
+pub mod config;
+pub mod diff;
+pub mod error;
+pub mod events;
+pub mod fee;
+pub mod keystore;
+pub mod memory;
+pub mod metrics;
+pub mod output;
+mod query;
+pub mod signer;
+pub mod source;
+pub mod storage;
+pub mod tx;
+
+#[cfg(feature = "async")]
+pub use async_filling::AsyncApiFilling;
+#[cfg(feature = "async")]
+pub use async_source::{AsyncStorageSource, WsSource};
+use balance::TokenInfo;
+pub use balances::AccountData;
 use balances::BalanceLock;
-use codec::{Decode, HasCompact};
-use hex::FromHex;
-use polkadot_primitives::{Balance, BlockNumber};
+use codec::{Decode, Encode, HasCompact};
+pub use error::{DumplingError, Result};
+pub use memory::MemorySource;
+pub use polkadot_primitives::{Balance, BlockNumber};
+use query::{
+    ledger_keys, nominator_list, nominator_map, nominators_by_validator, staking_lock, waitlist,
+    NominatorList, Staker,
+};
+use serde_json::{json, Value};
+pub use source::{NodeSource, StorageSource};
 pub use sp_core::{
-    crypto::{Pair, Public, Ss58AddressFormat, Ss58Codec},
-    sr25519,
+    crypto::{set_default_ss58_version, Pair, Public, Ss58AddressFormat, Ss58Codec},
+    ed25519, sr25519,
     storage::StorageKey,
     H256 as Hash,
 };
 pub use sp_runtime::{
-    generic::Header, traits::BlakeTwo256, AccountId32 as AccountId, MultiSignature,
+    generic::Header, traits::BlakeTwo256, AccountId32 as AccountId, MultiSignature, Perbill,
 };
 use sp_staking::SessionIndex;
-use staking::{ElectionCompute, EraIndex, Exposure, Nominations, StakingLedger, ValidatorPrefs};
-use std::collections::HashMap;
-use substrate_api_client::Api;
+use staking::{ElectionCompute, EraIndex, Exposure, Nominations, RewardPoint, ValidatorPrefs};
+pub use staking::{RewardDestination, StakingLedger, UnlockChunk};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Receiver};
+
+/// Default node endpoint used when none is provided
+pub const DEFAULT_URL: &str = "ws://127.0.0.1:9944";
+
+/// Default number of storage keys fetched per request
+pub const DEFAULT_BATCH_SIZE: usize = 256;
+
+/// Default number of keys per `state_getKeysPaged` page
+pub const DEFAULT_PAGE_SIZE: u32 = 1000;
 
-/// ApiFilling is a simple wrapper around substrate-api-client
+/// Eras of rewards kept by staking when `Staking::HistoryDepth` is not set
+pub const DEFAULT_HISTORY_DEPTH: EraIndex = 84;
+
+/// Storage values kept in snapshots, besides the staking maps
+const SNAPSHOT_VALUES: [(&str, &str); 5] = [
+    ("Staking", "ActiveEra"),
+    ("Staking", "CurrentEra"),
+    ("Staking", "QueuedElected"),
+    ("Session", "CurrentIndex"),
+    ("Session", "Validators"),
+];
+
+/// ApiFilling is a simple wrapper around a StorageSource, a node by default
 ///
 /// It has methods to easily fetch derived data from key prefix or combined rpc calls
-pub struct ApiFilling {
-    pub api: Api<sr25519::Pair>,
+pub struct ApiFilling<S: StorageSource = NodeSource> {
+    pub source: S,
+    pub chain: Chain,
+    /// Token of the chain, from the node's `system_properties` when available
+    pub token: TokenInfo,
+    /// Number of storage keys fetched per `state_queryStorageAt` request
+    pub batch_size: usize,
+    /// Number of keys per `state_getKeysPaged` page
+    pub page_size: u32,
 }
 
-impl ApiFilling {
+impl ApiFilling<NodeSource> {
     /// Create ApiFilling with:
     ///
-    /// url string - node_ip:node_port
-    /// chain - kusama, polkadot, westend
-    pub fn new(url: &str, format: &str) -> Self {
-        let ss58_version = match format {
-            "polkadot" => Ss58AddressFormat::PolkadotAccount,
-            "kusama" => Ss58AddressFormat::KusamaAccount,
-            "westend" => Ss58AddressFormat::SubstrateAccount,
-            _ => panic!("Format not supported"),
-        };
-        sp_core::crypto::set_default_ss58_version(ss58_version);
+    /// url string - ws://node_ip:node_port, wss://host or node_ip:node_port (defaults to ws://)
+    /// chain - kusama, polkadot, westend or a custom ss58 prefix
+    pub fn new(url: &str, chain: &str) -> Result<Self> {
+        let chain = Chain::from_str(chain)?;
+        let source = NodeSource::new(url)?;
+        Ok(Self::with_source(source, chain))
+    }
+}
 
+impl<S: StorageSource> ApiFilling<S> {
+    /// Create ApiFilling reading from any source, e.g. a MemorySource in tests
+    pub fn with_source(source: S, chain: Chain) -> Self {
+        set_default_ss58_version(chain.ss58_format());
+        let token = source
+            .rpc("system_properties", json!([]))
+            .ok()
+            .and_then(|p| TokenInfo::from_properties(&p))
+            .unwrap_or_else(|| TokenInfo::for_chain(chain));
         Self {
-            api: Api::<sr25519::Pair>::new(format!("ws://{}", url)),
+            source,
+            chain,
+            token,
+            batch_size: DEFAULT_BATCH_SIZE,
+            page_size: DEFAULT_PAGE_SIZE,
         }
     }
 
-    pub fn finalized_head(&self) -> (Option<Hash>, Option<Header<BlockNumber, BlakeTwo256>>) {
-        let hash = self.api.get_finalized_head();
-        let header = self.api.get_header(hash);
-        (hash, header)
+    /// Raw json-rpc call returning the `result` field, `Value::Null` if the node has none
+    pub fn rpc(&self, method: &str, params: Value) -> Result<Value> {
+        self.source.rpc(method, params)
+    }
+
+    pub fn finalized_head(&self) -> Result<(Hash, Header<BlockNumber, BlakeTwo256>)> {
+        let hash = self.source.finalized_head()?;
+        Ok((hash, self.header(hash)?))
+    }
+
+    pub fn header(&self, hash: Hash) -> Result<Header<BlockNumber, BlakeTwo256>> {
+        self.source.header(hash)
+    }
+
+    /// Hash of the block at `number` on the canonical chain
+    pub fn block_hash(&self, number: BlockNumber) -> Result<Hash> {
+        self.source.block_hash(Some(number))
+    }
+
+    /// Headers of the new finalized blocks, as they are finalized
+    pub fn finalized_heads(&self) -> Result<FinalizedHeads> {
+        let (sender, receiver) = channel();
+        self.source.subscribe_finalized_heads(sender)?;
+        Ok(FinalizedHeads { receiver })
+    }
+
+    /// Resolves a `0x` prefixed block hash or a block number
+    pub fn resolve_block(&self, at: &str) -> Result<Hash> {
+        if at.starts_with("0x") {
+            storage::parse_hash(at)
+        } else {
+            let number = at
+                .parse::<BlockNumber>()
+                .map_err(|_| DumplingError::Decode(format!("block {}", at)))?;
+            self.block_hash(number)
+        }
     }
 
-    pub fn active_era(&self, block_hash: Option<Hash>) -> Option<ActiveEraInfo> {
-        self.api
-            .get_storage_value::<ActiveEraInfo>("Staking", "ActiveEra", block_hash)
+    pub fn active_era(&self, block_hash: Option<Hash>) -> Result<ActiveEraInfo> {
+        self.storage_value("Staking", "ActiveEra", block_hash)
     }
 
-    pub fn planned_era(&self, block_hash: Option<Hash>) -> Option<EraIndex> {
-        self.api
-            .get_storage_value::<EraIndex>("Staking", "CurrentEra", block_hash)
+    pub fn planned_era(&self, block_hash: Option<Hash>) -> Result<EraIndex> {
+        self.storage_value("Staking", "CurrentEra", block_hash)
     }
 
-    pub fn session_index(&self, block_hash: Option<Hash>) -> Option<SessionIndex> {
-        self.api
-            .get_storage_value::<SessionIndex>("Session", "CurrentIndex", block_hash)
+    pub fn session_index(&self, block_hash: Option<Hash>) -> Result<SessionIndex> {
+        self.storage_value("Session", "CurrentIndex", block_hash)
     }
 
     pub fn waiting_validators(
         &self,
         block_hash: Option<Hash>,
-    ) -> HashMap<String, WaitingValidator> {
-        let mut waitlist = HashMap::new();
-        let key_prefix = self.api.get_storage_map_key_prefix("Staking", "Validators");
-        let keys_str = self.api.get_keys(key_prefix, block_hash).unwrap();
-        let v_to_n = Self::validators_to_nominators(self, block_hash);
-
-        for key in keys_str {
-            let storage_key = Self::string_to_key(&key);
-            let account_id = Self::key_to_account(&storage_key);
-
-            let opt_b: Option<Vec<BalanceLock<Balance>>> =
-                self.api.get_storage_map::<AccountId, _>(
-                    "Balances",
-                    "Locks",
-                    account_id.clone(),
-                    block_hash,
-                );
-            let mut staked: Balance = 0;
-            for i in opt_b.unwrap() {
-                if i.id == *b"staking " {
-                    staked = i.amount;
-                }
-            }
-            let prefs = self.api.get_storage_by_key_hash(storage_key, None).unwrap();
-
-            let nominators = match v_to_n.get(&account_id) {
-                Some(n) => n.clone(),
-                None => vec![String::from("None")]
-            };
-
-            let ledger = self.api.get_storage_map::<AccountId, _>(
-                "Staking",
-                "Ledger",
-                account_id.clone(),
-                None,
-            );
-
-            waitlist.insert(
-                account_id.to_ss58check(),
-                WaitingValidator {
-                    staked: staked,
-                    prefs: prefs,
-                    nominators: nominators,
-                    ledger: ledger,
-                },
-            );
-        }
-        waitlist
+    ) -> Result<HashMap<String, WaitingValidator>> {
+        let block_hash = Some(self.pin(block_hash)?);
+        let v_to_n = Self::validators_to_nominators(self, block_hash)?;
+        self.waiting_backed_by(&v_to_n, block_hash)
+    }
+
+    /// Waiting validators with their nominators from `v_to_n`
+    fn waiting_backed_by(
+        &self,
+        v_to_n: &HashMap<AccountId, Vec<String>>,
+        block_hash: Option<Hash>,
+    ) -> Result<HashMap<String, WaitingValidator>> {
+        let storage_keys = self.map_keys("Staking", "Validators", block_hash)?;
+        let stashes = storage_keys
+            .iter()
+            .map(storage::account_from_key)
+            .collect::<Result<Vec<AccountId>>>()?;
+
+        let prefs = self.decode_batch::<ValidatorPrefs>(&storage_keys, block_hash)?;
+        let staked = self.staked(&stashes, block_hash)?;
+        let ledgers = self.ledgers(&stashes, block_hash)?;
+
+        waitlist(stashes, prefs, staked, ledgers, v_to_n)
     }
 
     pub fn validators_to_nominators(
         &self,
         block_hash: Option<Hash>,
-    ) -> HashMap<AccountId, Vec<String>> {
-        let mut v: HashMap<AccountId, Vec<String>> = HashMap::new();
-        let nom_list = Self::get_nominators(self, block_hash);
-        for n in nom_list {
-            let nom_id = n.1.to_ss58check();
-            let nominations = n.2;
-            if let Some(n) = nominations {
-                for t in n.targets {
-                    if let Some(nom_vec) = v.get_mut(&t) {
-                        nom_vec.push(nom_id.clone());
-                    } else {
-                        v.insert(t, vec![nom_id.clone()]);
+    ) -> Result<HashMap<AccountId, Vec<String>>> {
+        let nom_list = Self::get_nominators(self, block_hash)?;
+        Ok(nominators_by_validator(nom_list))
+    }
+
+    pub fn session_validators(&self, block_hash: Option<Hash>) -> Result<Vec<AccountId>> {
+        self.storage_value("Session", "Validators", block_hash)
+    }
+
+    /// Election result queued for the next era, `None` outside of the election window
+    pub fn queued_validators(
+        &self,
+        block_hash: Option<Hash>,
+    ) -> Result<Option<ElectionResult<AccountId, Balance>>> {
+        self.decode_value(&storage::value_key("Staking", "QueuedElected"), block_hash)
+    }
+
+    /// Nonce and balances of the account, `None` when it does not exist
+    pub fn account_info(
+        &self,
+        account: &AccountId,
+        block_hash: Option<Hash>,
+    ) -> Result<Option<AccountInfo>> {
+        self.decode_value(&storage::account_key(account), block_hash)
+    }
+
+    pub fn nominators(
+        &self,
+        block_hash: Option<Hash>,
+    ) -> Result<HashMap<String, Option<Nominator>>> {
+        let block_hash = Some(self.pin(block_hash)?);
+        let nom_list = Self::get_nominators(self, block_hash)?;
+        self.nominators_staked(nom_list, block_hash)
+    }
+
+    /// Nominators of `nom_list` with their staked balances
+    fn nominators_staked(
+        &self,
+        nom_list: NominatorList,
+        block_hash: Option<Hash>,
+    ) -> Result<HashMap<String, Option<Nominator>>> {
+        let accounts: Vec<AccountId> = nom_list.iter().map(|n| n.1.clone()).collect();
+        let staked = self.staked(&accounts, block_hash)?;
+
+        Ok(nominator_map(nom_list, staked))
+    }
+
+    /// Total and per-validator reward points of `era`, none until the era starts
+    pub fn era_reward_points(
+        &self,
+        era: EraIndex,
+        block_hash: Option<Hash>,
+    ) -> Result<EraRewardPoints<AccountId>> {
+        Ok(self
+            .decode_value(&storage::reward_points_key(era), block_hash)?
+            .unwrap_or_else(|| EraRewardPoints {
+                total: 0,
+                individual: BTreeMap::new(),
+            }))
+    }
+
+    /// Eras with rewards still to be paid out to `stash`, with the estimated payout
+    ///
+    /// For a validator, its own eras. For a nominator, the eras of the validators it backed in
+    /// them: its current targets once they were elected, any rewarded validator before that,
+    /// so former targets are found too. Only eras within `Staking::HistoryDepth` can still be
+    /// paid out, and only the exposures of eras their validator did not claim are read, era by
+    /// era until the stash was found in as many exposures as it can have.
+    pub fn unclaimed_eras(
+        &self,
+        stash: &AccountId,
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<UnclaimedPayout>> {
+        let block_hash = Some(self.pin(block_hash)?);
+        let active_era = self.active_era(block_hash)?.index;
+        let current_era = self.planned_era(block_hash)?;
+        let history_depth = self
+            .decode_value(&storage::value_key("Staking", "HistoryDepth"), block_hash)?
+            .unwrap_or(DEFAULT_HISTORY_DEPTH);
+        let first_era = current_era.saturating_sub(history_depth);
+        // Rewards of the active era are only known once it ends
+        let eras: Vec<EraIndex> = (first_era..active_era).collect();
+
+        let reward_keys: Vec<StorageKey> =
+            eras.iter().map(|e| storage::era_reward_key(*e)).collect();
+        let era_rewards = self.decode_batch::<Balance>(&reward_keys, block_hash)?;
+        let points_keys: Vec<StorageKey> = eras
+            .iter()
+            .map(|e| storage::reward_points_key(*e))
+            .collect();
+        let points = self.decode_batch::<EraRewardPoints<AccountId>>(&points_keys, block_hash)?;
+
+        let staker = if query::validated(stash, &points) {
+            Staker::Validator
+        } else {
+            Staker::Nominator(self.decode_value(&storage::nominators_key(stash), block_hash)?)
+        };
+        let candidates = query::exposure_candidates(stash, &staker, &eras, &points);
+        // Payouts need the validator's ledger, which lists the eras it claimed
+        let validators = query::candidate_validators(&candidates);
+        let ledgers = self.ledgers(&validators, block_hash)?;
+        let claimed = query::claimed_eras(&validators, ledgers);
+
+        let mut exposed = Vec::new();
+        for (era, candidates) in eras.iter().zip(candidates) {
+            let pending = query::pending(*era, candidates, &claimed);
+            let mut found = 0;
+            for chunk in pending.chunks(self.batch_size.max(1)) {
+                let keys: Vec<StorageKey> = chunk
+                    .iter()
+                    .map(|v| storage::eras_stakers_clipped_key(*era, v))
+                    .collect();
+                let exposures =
+                    self.decode_batch::<Exposure<AccountId, Balance>>(&keys, block_hash)?;
+                for (validator, exposure) in chunk.iter().zip(exposures) {
+                    match exposure {
+                        Some(e) if query::exposed_to(stash, validator, &e) => {
+                            exposed.push((*era, (*validator).clone(), e));
+                            found += 1;
+                        }
+                        _ => {}
                     }
                 }
+                if found >= staker.max_exposures() {
+                    break;
+                }
             }
         }
-        v
+        let prefs_keys: Vec<StorageKey> = exposed
+            .iter()
+            .map(|(era, validator, _)| storage::eras_validator_prefs_key(*era, validator))
+            .collect();
+        let prefs = self.decode_batch::<ValidatorPrefs>(&prefs_keys, block_hash)?;
+
+        Ok(query::unclaimed_payouts(
+            stash,
+            first_era,
+            exposed,
+            prefs,
+            &era_rewards,
+            &points,
+        ))
     }
 
-    pub fn session_validators(&self, block_hash: Option<Hash>) -> Option<Vec<AccountId>> {
-        self.api
-            .get_storage_value::<Vec<AccountId>>("Session", "Validators", block_hash)
+    /// Changes to the waiting validators and nominators from one block to another
+    pub fn staking_diff(&self, from: Hash, to: Hash) -> Result<diff::StakingDiff> {
+        let (validators_before, nominators_before) = self.staking_sets(from)?;
+        let (validators_after, nominators_after) = self.staking_sets(to)?;
+        Ok(diff::diff(
+            &validators_before,
+            &nominators_before,
+            &validators_after,
+            &nominators_after,
+        ))
     }
 
-    pub fn queued_validators(
+    /// Waiting validators and nominators at `block_hash`, the nominators are read once
+    fn staking_sets(
         &self,
+        block_hash: Hash,
+    ) -> Result<(
+        HashMap<String, WaitingValidator>,
+        HashMap<String, Option<Nominator>>,
+    )> {
+        let block_hash = Some(block_hash);
+        let nom_list = self.get_nominators(block_hash)?;
+        let v_to_n = nominators_by_validator(nom_list.clone());
+        let validators = self.waiting_backed_by(&v_to_n, block_hash)?;
+        Ok((validators, self.nominators_staked(nom_list, block_hash)?))
+    }
+
+    /// Metrics for the exporter, all read at the same block
+    pub fn metrics(&self, block_hash: Option<Hash>) -> Result<metrics::Metrics> {
+        let hash = self.pin(block_hash)?;
+        let block_hash = Some(hash);
+        let nom_list = self.get_nominators(block_hash)?;
+        let nominators = nom_list.len();
+        let mut validator_nominators: Vec<(String, usize)> = nominators_by_validator(nom_list)
+            .into_iter()
+            .map(|(stash, n)| (stash.to_ss58check(), n.len()))
+            .collect();
+        validator_nominators.sort();
+        let exposures = match self.queued_validators(block_hash)? {
+            Some(r) => r
+                .exposures
+                .iter()
+                .map(|(stash, e)| (stash.to_ss58check(), e.total, e.own))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        Ok(metrics::Metrics {
+            block: self.header(hash)?.number,
+            active_era: self.active_era(block_hash)?.index,
+            planned_era: self.planned_era(block_hash)?,
+            session_index: self.session_index(block_hash)?,
+            waiting_validators: self.map_keys("Staking", "Validators", block_hash)?.len(),
+            nominators,
+            exposures,
+            validator_nominators,
+        })
+    }
+
+    /// State of `stashes` the alert rules look at, with nominators counted from `v_to_n`
+    pub fn stash_status(
+        &self,
+        stashes: &[AccountId],
+        v_to_n: &HashMap<AccountId, Vec<String>>,
         block_hash: Option<Hash>,
-    ) -> Option<ElectionResult<AccountId, Balance>> {
-        self.api
-            .get_storage_value::<ElectionResult<AccountId, Balance>>(
-                "Staking",
-                "QueuedElected",
-                block_hash,
-            )
-    }
-
-    pub fn nominators(&self, block_hash: Option<Hash>) -> HashMap<String, Option<Nominator>> {
-        let nom_list = Self::get_nominators(self, block_hash);
-        let mut nominations = HashMap::new();
-        for n in nom_list {
-            let account_id = n.1;
-            let nom = n.2;
-            if let Some(n) = nom {
-                let opt_b: Option<Vec<BalanceLock<Balance>>> =
-                    self.api.get_storage_map::<AccountId, _>(
-                        "Balances",
-                        "Locks",
-                        account_id.clone(),
-                        block_hash,
-                    );
-                let mut b: Balance = 0;
-                for i in opt_b.unwrap() {
-                    if i.id == *b"staking " {
-                        b = i.amount;
-                    }
-                }
-                nominations.insert(
-                    account_id.to_ss58check(),
-                    Some(Nominator {
-                        nominations: n,
-                        staked: b,
-                    }),
-                );
-            } else {
-                nominations.insert(account_id.to_ss58check(), None);
-            };
+    ) -> Result<alert::Status> {
+        let hash = self.pin(block_hash)?;
+        let block_hash = Some(hash);
+        let block = self.header(hash)?.number;
+        let session_index = self.session_index(block_hash)?;
+        let session_validators = self.session_validators(block_hash)?;
+        let queued = self.queued_validators(block_hash)?;
+        let prefs_keys: Vec<StorageKey> = stashes.iter().map(storage::validators_key).collect();
+        let prefs = self.decode_batch::<ValidatorPrefs>(&prefs_keys, block_hash)?;
+        let online = self.online(stashes, &session_validators, session_index, block_hash)?;
+
+        let stashes = stashes
+            .iter()
+            .zip(prefs)
+            .zip(online)
+            .map(|((stash, prefs), online)| alert::StashStatus {
+                stash: stash.clone(),
+                in_session: session_validators.contains(stash),
+                queued: queued.as_ref().map(|q| q.elected_stashes.contains(stash)),
+                commission: prefs.map(|p| p.commission),
+                nominators: v_to_n.get(stash).map_or(0, |n| n.len()),
+                online,
+            })
+            .collect();
+        Ok(alert::Status {
+            block,
+            session_index,
+            stashes,
+        })
+    }
+
+    /// One page of at most `count` keys under `prefix`, starting after `start_key`
+    pub fn keys_paged(
+        &self,
+        prefix: &StorageKey,
+        count: u32,
+        start_key: Option<&StorageKey>,
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<StorageKey>> {
+        self.source.keys_paged(prefix, count, start_key, block_hash)
+    }
+
+    /// Streams every key under `prefix`, `page_size` keys per `state_getKeysPaged` request
+    ///
+    /// All pages are read at the same block, the latest if none is given.
+    pub fn iter_keys(&self, prefix: StorageKey, block_hash: Option<Hash>) -> Result<KeyPages<S>> {
+        Ok(KeyPages {
+            filling: self,
+            prefix,
+            block_hash: Some(self.pin(block_hash)?),
+            start_key: None,
+            page: Vec::new().into_iter(),
+            done: false,
+        })
+    }
+
+    /// Raw values of `keys` at the block, fetched `batch_size` keys per request
+    pub fn storage_batch(
+        &self,
+        keys: &[StorageKey],
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut values = Vec::with_capacity(keys.len());
+        for chunk in keys.chunks(self.batch_size.max(1)) {
+            values.extend(self.source.storage_batch(chunk, block_hash)?);
+        }
+        Ok(values)
+    }
+
+    /// Decoded values of `keys`, `None` for empty entries
+    pub fn decode_batch<V: Decode>(
+        &self,
+        keys: &[StorageKey],
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<Option<V>>> {
+        storage::decode_values(keys, self.storage_batch(keys, block_hash)?)
+    }
+
+    /// Copies every entry dumpling reads at the block, the latest if none is given
+    ///
+    /// The copy answers all queries offline, e.g. saved to a file and replayed later.
+    pub fn snapshot(&self, block_hash: Option<Hash>) -> Result<MemorySource> {
+        let hash = self.pin(block_hash)?;
+        let block_hash = Some(hash);
+        let mut snapshot = MemorySource::new(self.header(hash)?);
+        snapshot.properties = self
+            .rpc("system_properties", json!([]))
+            .ok()
+            .filter(|p| !p.is_null())
+            .map(|p| p.to_string());
+
+        let mut keys: Vec<StorageKey> = SNAPSHOT_VALUES
+            .iter()
+            .map(|(module, item)| storage::value_key(module, item))
+            .collect();
+        keys.extend(self.map_keys("Staking", "Validators", block_hash)?);
+        keys.extend(self.map_keys("Staking", "Nominators", block_hash)?);
+        let stashes = keys[SNAPSHOT_VALUES.len()..]
+            .iter()
+            .map(storage::account_from_key)
+            .collect::<Result<Vec<AccountId>>>()?;
+        keys.extend(stashes.iter().map(storage::locks_key));
+        let bonded_keys: Vec<StorageKey> = stashes.iter().map(storage::bonded_key).collect();
+        let controllers = self.decode_batch::<AccountId>(&bonded_keys, block_hash)?;
+        keys.extend(bonded_keys);
+        keys.extend(ledger_keys(&stashes, &controllers));
+        if let Ok(active) = self.active_era(block_hash) {
+            keys.push(storage::reward_points_key(active.index));
+        }
+
+        let values = self.storage_batch(&keys, block_hash)?;
+        for (key, value) in keys.into_iter().zip(values) {
+            if let Some(value) = value {
+                snapshot.insert(key, value);
+            }
         }
-        nominations
+        Ok(snapshot)
+    }
+
+    fn get_nominators(&self, block_hash: Option<Hash>) -> Result<NominatorList> {
+        let storage_keys = self.map_keys("Staking", "Nominators", block_hash)?;
+        let noms = self.decode_batch::<Nominations<AccountId>>(&storage_keys, block_hash)?;
+        nominator_list(storage_keys, noms)
+    }
+
+    /// Pins queries spanning several requests to one block, the latest if none is given
+    fn pin(&self, block_hash: Option<Hash>) -> Result<Hash> {
+        match block_hash {
+            Some(hash) => Ok(hash),
+            None => self.source.block_hash(None),
+        }
+    }
+
+    fn storage_value<V: Decode>(
+        &self,
+        module: &str,
+        item: &str,
+        block_hash: Option<Hash>,
+    ) -> Result<V> {
+        self.decode_value(&storage::value_key(module, item), block_hash)?
+            .ok_or_else(|| DumplingError::MissingStorage(format!("{}::{}", module, item)))
+    }
+
+    fn decode_value<V: Decode>(
+        &self,
+        key: &StorageKey,
+        block_hash: Option<Hash>,
+    ) -> Result<Option<V>> {
+        let value = self.source.storage(key, block_hash)?;
+        Ok(storage::decode_values(&[key.clone()], vec![value])?
+            .pop()
+            .flatten())
+    }
+
+    fn map_keys(
+        &self,
+        module: &str,
+        item: &str,
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<StorageKey>> {
+        self.iter_keys(storage::value_key(module, item), block_hash)?
+            .collect()
+    }
+
+    /// Amounts locked by staking
+    fn staked(&self, accounts: &[AccountId], block_hash: Option<Hash>) -> Result<Vec<Balance>> {
+        let keys: Vec<StorageKey> = accounts.iter().map(storage::locks_key).collect();
+        Ok(self
+            .decode_batch::<Vec<BalanceLock<Balance>>>(&keys, block_hash)?
+            .into_iter()
+            .map(|locks| staking_lock(&locks.unwrap_or_default()))
+            .collect())
     }
 
-    fn get_nominators(
+    /// Whether the stashes sent a heartbeat or authored a block in the session
+    fn online(
         &self,
+        stashes: &[AccountId],
+        session_validators: &[AccountId],
+        session_index: SessionIndex,
         block_hash: Option<Hash>,
-    ) -> Vec<(StorageKey, AccountId, Option<Nominations<AccountId>>)> {
-        let key_prefix = self.api.get_storage_map_key_prefix("Staking", "Nominators");
-        let keys_str = self.api.get_keys(key_prefix, block_hash).unwrap();
-        let mut nom_list = Vec::new();
-        for key in keys_str {
-            let storage_key = Self::string_to_key(&key);
-            let account_id = Self::key_to_account(&storage_key);
-            let nom = self
-                .api
-                .get_storage_by_key_hash(storage_key.clone(), block_hash);
-            nom_list.push((storage_key, account_id, nom));
+    ) -> Result<Vec<bool>> {
+        let heartbeat_keys: Vec<StorageKey> = stashes
+            .iter()
+            .map(|stash| {
+                // Heartbeats are indexed by position in the session validators
+                let auth_index = session_validators
+                    .iter()
+                    .position(|v| v == stash)
+                    .unwrap_or_else(|| session_validators.len());
+                storage::heartbeat_key(session_index, auth_index as u32)
+            })
+            .collect();
+        let heartbeats = self.storage_batch(&heartbeat_keys, block_hash)?;
+        let authored_keys: Vec<StorageKey> = stashes
+            .iter()
+            .map(|stash| storage::authored_blocks_key(session_index, stash))
+            .collect();
+        let authored = self.decode_batch::<u32>(&authored_keys, block_hash)?;
+        Ok(heartbeats
+            .iter()
+            .zip(authored)
+            .map(|(heartbeat, blocks)| heartbeat.is_some() || blocks.unwrap_or(0) > 0)
+            .collect())
+    }
+
+    /// Ledgers of the stashes, looked up through their bonded controllers
+    fn ledgers(
+        &self,
+        stashes: &[AccountId],
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<Option<StakingLedger<AccountId, Balance>>>> {
+        let bonded_keys: Vec<StorageKey> = stashes.iter().map(storage::bonded_key).collect();
+        let controllers = self.decode_batch::<AccountId>(&bonded_keys, block_hash)?;
+        self.decode_batch(&ledger_keys(stashes, &controllers), block_hash)
+    }
+}
+
+/// Parses an ss58 address into an AccountId
+pub fn parse_account(address: &str) -> Result<AccountId> {
+    AccountId::from_ss58check(address)
+        .map_err(|_| DumplingError::InvalidAddress(address.to_string()))
+}
+
+/// Iterator over the storage keys under a prefix, fetching a page at a time
+pub struct KeyPages<'a, S: StorageSource = NodeSource> {
+    filling: &'a ApiFilling<S>,
+    prefix: StorageKey,
+    block_hash: Option<Hash>,
+    start_key: Option<StorageKey>,
+    page: std::vec::IntoIter<StorageKey>,
+    done: bool,
+}
+
+impl<'a, S: StorageSource> Iterator for KeyPages<'a, S> {
+    type Item = Result<StorageKey>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(key) = self.page.next() {
+                return Some(Ok(key));
+            }
+            if self.done {
+                return None;
+            }
+            let page_size = self.filling.page_size.max(1);
+            match self.filling.keys_paged(
+                &self.prefix,
+                page_size,
+                self.start_key.as_ref(),
+                self.block_hash,
+            ) {
+                Ok(keys) => {
+                    self.done = keys.len() < page_size as usize;
+                    self.start_key = keys.last().cloned();
+                    self.page = keys.into_iter();
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
         }
-        nom_list
     }
+}
+
+/// Finalized headers from `chain_subscribeFinalizedHeads`, ends when the subscription does
+pub struct FinalizedHeads {
+    receiver: Receiver<String>,
+}
 
-    fn string_to_key(key: &str) -> StorageKey {
-        StorageKey(Vec::from_hex(&key[2..]).unwrap())
+impl Iterator for FinalizedHeads {
+    type Item = Result<Header<BlockNumber, BlakeTwo256>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.receiver.recv().ok()?;
+        Some(
+            serde_json::from_str(&header)
+                .map_err(|_| DumplingError::Decode(format!("finalized header {}", header))),
+        )
+    }
+}
+
+/// Networks supported by dumpling, or any other network by its ss58 prefix
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Chain {
+    Polkadot,
+    Kusama,
+    Westend,
+    Custom(u8),
+}
+
+impl Chain {
+    pub fn ss58_format(&self) -> Ss58AddressFormat {
+        match self {
+            Chain::Polkadot => Ss58AddressFormat::PolkadotAccount,
+            Chain::Kusama => Ss58AddressFormat::KusamaAccount,
+            Chain::Westend => Ss58AddressFormat::SubstrateAccount,
+            Chain::Custom(prefix) => Ss58AddressFormat::Custom(*prefix),
+        }
     }
+}
 
-    fn key_to_account(s: &StorageKey) -> AccountId {
-        sr25519::Public::from_slice(&s.0[40..]).into()
+impl FromStr for Chain {
+    type Err = DumplingError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "polkadot" => Ok(Chain::Polkadot),
+            "kusama" => Ok(Chain::Kusama),
+            "westend" => Ok(Chain::Westend),
+            other => other
+                .parse::<u8>()
+                .map(Chain::Custom)
+                .map_err(|_| DumplingError::UnsupportedChain(s.to_string())),
+        }
     }
 }
 
@@ -221,6 +732,16 @@ pub struct Nominator {
     pub staked: Balance,
 }
 
+/// Era reward not paid out yet
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnclaimedPayout {
+    pub era: EraIndex,
+    /// Validator whose `payout_stakers` pays the reward
+    pub validator: AccountId,
+    /// Estimated share of the stash
+    pub payout: Balance,
+}
+
 pub struct WaitingValidator {
     pub staked: Balance,
     pub prefs: ValidatorPrefs,
@@ -229,15 +750,43 @@ pub struct WaitingValidator {
 }
 
 // A copy of the ElectionResults from staking to make fields public
-#[derive(Decode)]
+#[derive(Decode, Encode)]
 pub struct ElectionResult<AccountId, Balance: HasCompact> {
     pub elected_stashes: Vec<AccountId>,
     pub exposures: Vec<(AccountId, Exposure<AccountId, Balance>)>,
     pub compute: ElectionCompute,
 }
 
+// A copy of the EraRewardPoints from staking to make fields public
+#[derive(Decode, Encode)]
+pub struct EraRewardPoints<AccountId: Ord> {
+    /// Total number of points. Equals the sum of reward points for each validator.
+    pub total: RewardPoint,
+    /// The reward points earned by a given validator.
+    pub individual: BTreeMap<AccountId, RewardPoint>,
+}
+
+impl<AccountId: Ord> EraRewardPoints<AccountId> {
+    /// Validators by points, highest first
+    pub fn ranked(&self) -> Vec<(&AccountId, RewardPoint)> {
+        let mut ranked: Vec<(&AccountId, RewardPoint)> =
+            self.individual.iter().map(|(v, p)| (v, *p)).collect();
+        ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        ranked
+    }
+}
+
+// A copy of the AccountInfo from frame_system, with the AccountData of balances
+#[derive(Clone, Debug, Decode, Encode, PartialEq)]
+pub struct AccountInfo {
+    pub nonce: u32,
+    /// Modules depending on the account
+    pub refcount: u8,
+    pub data: AccountData<Balance>,
+}
+
 // A copy of the ActiveEraInfo from staking to make fields public
-#[derive(Decode)]
+#[derive(Decode, Encode)]
 pub struct ActiveEraInfo {
     /// Index of era.
     pub index: EraIndex,
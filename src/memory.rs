@@ -0,0 +1,175 @@
+//! In memory StorageSource holding the state of a single block
+
+use crate::{
+    source::StorageSource,
+    storage::{self, Hasher},
+    BlakeTwo256, BlockNumber, DumplingError, Hash, Header, Result, StorageKey,
+};
+use codec::{Decode, Encode};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Leading bytes of snapshot files
+const SNAPSHOT_MAGIC: [u8; 8] = *b"dumpling";
+const SNAPSHOT_VERSION: u8 = 1;
+
+/// State of one block, populated from SCALE encoded fixtures
+///
+/// ```ignore
+/// let mut state = MemorySource::new(header);
+/// state.insert_value("Staking", "CurrentEra", &12u32);
+/// state.insert_map("Staking", "Validators", Hasher::Twox64Concat, &stash, &prefs);
+/// let d = ApiFilling::with_source(state, Chain::Kusama);
+/// ```
+///
+/// Saved to and loaded from snapshot files, see ApiFilling::snapshot.
+#[derive(Clone, Debug)]
+pub struct MemorySource {
+    pub header: Header<BlockNumber, BlakeTwo256>,
+    /// `system_properties` of the node the state was read from, as json
+    pub properties: Option<String>,
+    pub entries: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl MemorySource {
+    pub fn new(header: Header<BlockNumber, BlakeTwo256>) -> Self {
+        Self {
+            header,
+            properties: None,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// No state at all, for commands that do not read the chain
+    pub fn empty() -> Self {
+        Self::new(Header::new(
+            0,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        ))
+    }
+
+    /// Hash of the block the state belongs to
+    pub fn hash(&self) -> Hash {
+        self.header.hash()
+    }
+
+    /// Raw value at `key`, already SCALE encoded
+    pub fn insert(&mut self, key: StorageKey, value: Vec<u8>) {
+        self.entries.insert(key.0, value);
+    }
+
+    pub fn insert_value<V: Encode>(&mut self, module: &str, item: &str, value: &V) {
+        self.insert(storage::value_key(module, item), value.encode());
+    }
+
+    pub fn insert_map<K: Encode, V: Encode>(
+        &mut self,
+        module: &str,
+        item: &str,
+        hasher: Hasher,
+        key: &K,
+        value: &V,
+    ) {
+        self.insert(storage::map_key(module, item, hasher, key), value.encode());
+    }
+
+    /// Writes the state to `path`
+    ///
+    /// A snapshot is `dumpling`, a version byte, then the SCALE encoded
+    /// header, properties and entries.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut bytes = SNAPSHOT_MAGIC.to_vec();
+        bytes.push(SNAPSHOT_VERSION);
+        (&self.header, &self.properties, &self.entries).encode_to(&mut bytes);
+        fs::write(path, bytes).map_err(|e| DumplingError::Io(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Reads a state written by `save`
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes =
+            fs::read(path).map_err(|e| DumplingError::Io(format!("{}: {}", path.display(), e)))?;
+        let invalid = || DumplingError::Decode(format!("snapshot {}", path.display()));
+        if bytes.len() < 9 || bytes[..8] != SNAPSHOT_MAGIC || bytes[8] != SNAPSHOT_VERSION {
+            return Err(invalid());
+        }
+        let (header, properties, entries) =
+            Decode::decode(&mut &bytes[9..]).map_err(|_| invalid())?;
+        Ok(Self {
+            header,
+            properties,
+            entries,
+        })
+    }
+
+    /// Queries at any other block than ours cannot be answered
+    fn check_block(&self, block_hash: Option<Hash>) -> Result<()> {
+        match block_hash {
+            Some(hash) if hash != self.hash() => Err(DumplingError::MissingStorage(format!(
+                "State at {:?}",
+                hash
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl StorageSource for MemorySource {
+    fn storage(&self, key: &StorageKey, block_hash: Option<Hash>) -> Result<Option<Vec<u8>>> {
+        self.check_block(block_hash)?;
+        Ok(self.entries.get(&key.0).cloned())
+    }
+
+    fn keys_paged(
+        &self,
+        prefix: &StorageKey,
+        count: u32,
+        start_key: Option<&StorageKey>,
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<StorageKey>> {
+        self.check_block(block_hash)?;
+        Ok(self
+            .entries
+            .range(prefix.0.clone()..)
+            .map(|(k, _)| k)
+            .take_while(|k| k.starts_with(&prefix.0))
+            .filter(|k| start_key.map_or(true, |s| **k > s.0))
+            .take(count as usize)
+            .map(|k| StorageKey(k.clone()))
+            .collect())
+    }
+
+    fn finalized_head(&self) -> Result<Hash> {
+        Ok(self.hash())
+    }
+
+    fn header(&self, hash: Hash) -> Result<Header<BlockNumber, BlakeTwo256>> {
+        self.check_block(Some(hash))?;
+        Ok(self.header.clone())
+    }
+
+    fn block_hash(&self, number: Option<BlockNumber>) -> Result<Hash> {
+        match number {
+            Some(n) if n != self.header.number => {
+                Err(DumplingError::MissingStorage(format!("Block #{}", n)))
+            }
+            _ => Ok(self.hash()),
+        }
+    }
+
+    /// Answers `system_properties` from the snapshot, nothing else
+    fn rpc(&self, method: &str, _params: Value) -> Result<Value> {
+        match (method, &self.properties) {
+            ("system_properties", Some(properties)) => serde_json::from_str(properties)
+                .map_err(|_| DumplingError::Decode(format!("properties {}", properties))),
+            _ => Err(DumplingError::Unsupported(format!(
+                "{} without a node connection",
+                method
+            ))),
+        }
+    }
+}
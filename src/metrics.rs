@@ -0,0 +1,117 @@
+//! Prometheus metrics of the staking state, see ApiFilling::metrics
+
+use crate::{Balance, BlockNumber};
+use sp_staking::SessionIndex;
+use staking::EraIndex;
+use std::fmt::Write;
+
+/// Staking state at a block, balances in planck
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    pub block: BlockNumber,
+    pub active_era: EraIndex,
+    pub planned_era: EraIndex,
+    pub session_index: SessionIndex,
+    /// Validator candidates, elected or not
+    pub waiting_validators: usize,
+    pub nominators: usize,
+    /// Stash, total and own exposure from the queued election result
+    pub exposures: Vec<(String, Balance, Balance)>,
+    /// Stash and number of nominators backing it
+    pub validator_nominators: Vec<(String, usize)>,
+}
+
+impl Metrics {
+    /// Text exposition format, version 0.0.4
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        gauge(
+            &mut out,
+            "dumpling_finalized_block_number",
+            "Finalized block",
+            &[("", self.block as f64)],
+        );
+        gauge(
+            &mut out,
+            "dumpling_active_era",
+            "Active era",
+            &[("", self.active_era as f64)],
+        );
+        gauge(
+            &mut out,
+            "dumpling_planned_era",
+            "Planned era",
+            &[("", self.planned_era as f64)],
+        );
+        gauge(
+            &mut out,
+            "dumpling_session_index",
+            "Current session index",
+            &[("", self.session_index as f64)],
+        );
+        gauge(
+            &mut out,
+            "dumpling_waiting_validators",
+            "Validator candidates",
+            &[("", self.waiting_validators as f64)],
+        );
+        gauge(
+            &mut out,
+            "dumpling_nominators",
+            "Nominators",
+            &[("", self.nominators as f64)],
+        );
+
+        let total: Vec<(String, f64)> = self
+            .exposures
+            .iter()
+            .map(|(stash, total, _)| (stash_label(stash), *total as f64))
+            .collect();
+        let own: Vec<(String, f64)> = self
+            .exposures
+            .iter()
+            .map(|(stash, _, own)| (stash_label(stash), *own as f64))
+            .collect();
+        let nominators: Vec<(String, f64)> = self
+            .validator_nominators
+            .iter()
+            .map(|(stash, n)| (stash_label(stash), *n as f64))
+            .collect();
+        gauge(
+            &mut out,
+            "dumpling_exposure_total",
+            "Total exposure of queued validators, in planck",
+            &labelled(&total),
+        );
+        gauge(
+            &mut out,
+            "dumpling_exposure_own",
+            "Own exposure of queued validators, in planck",
+            &labelled(&own),
+        );
+        gauge(
+            &mut out,
+            "dumpling_validator_nominators",
+            "Nominators backing the validator",
+            &labelled(&nominators),
+        );
+        out
+    }
+}
+
+fn stash_label(stash: &str) -> String {
+    format!("{{stash=\"{}\"}}", stash)
+}
+
+fn labelled(samples: &[(String, f64)]) -> Vec<(&str, f64)> {
+    samples.iter().map(|(l, v)| (l.as_str(), *v)).collect()
+}
+
+/// Writes a gauge with one sample per label set, `""` for none
+fn gauge(out: &mut String, name: &str, help: &str, samples: &[(&str, f64)]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{}{} {}", name, labels, value);
+    }
+}
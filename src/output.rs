@@ -0,0 +1,405 @@
+use crate::{
+    balance::BalanceFormatter, diff::StakingDiff, fee::FeeInfo, keystore::KeyEntry, AccountId,
+    ActiveEraInfo, DumplingError, ElectionResult, EraRewardPoints, Nominator, Result, Ss58Codec,
+    UnclaimedPayout, WaitingValidator,
+};
+use polkadot_primitives::{Balance, BlockNumber};
+use serde::Serialize;
+use serde_json::Value;
+use sp_runtime::Perbill;
+use sp_staking::SessionIndex;
+use staking::{EraIndex, Exposure, RewardPoint};
+use std::str::FromStr;
+
+/// Output formats supported by every subcommand
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+    Yaml,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            "yaml" => Ok(OutputFormat::Yaml),
+            _ => Err(format!("Output format not supported: {}", s)),
+        }
+    }
+}
+
+/// Serializes `value` as json or yaml.
+///
+/// For csv, `value` is expected to be a list of records, one row each.
+/// Lists of plain values are joined with `;` and nested records are written as json.
+pub fn render<T: Serialize>(format: OutputFormat, value: &T) -> Result<String> {
+    match format {
+        OutputFormat::Json => serde_json::to_string_pretty(value).map_err(encode_error),
+        OutputFormat::Yaml => serde_yaml::to_string(value).map_err(encode_error),
+        OutputFormat::Csv => to_csv(serde_json::to_value(value).map_err(encode_error)?),
+        OutputFormat::Table => Err(DumplingError::Encode(
+            "table, it is rendered by the cli".to_string(),
+        )),
+    }
+}
+
+fn encode_error<E: std::fmt::Display>(e: E) -> DumplingError {
+    DumplingError::Encode(e.to_string())
+}
+
+fn to_csv(value: Value) -> Result<String> {
+    let rows = match value {
+        Value::Array(rows) => rows,
+        row => vec![row],
+    };
+    let mut w = csv::Writer::from_writer(vec![]);
+    if let Some(Value::Object(first)) = rows.first() {
+        w.write_record(first.keys()).map_err(encode_error)?;
+    }
+    for row in rows {
+        let cells: Vec<String> = match row {
+            Value::Object(fields) => fields.into_iter().map(|(_, v)| csv_cell(v)).collect(),
+            other => vec![csv_cell(other)],
+        };
+        w.write_record(cells).map_err(encode_error)?;
+    }
+    let bytes = w.into_inner().map_err(encode_error)?;
+    String::from_utf8(bytes).map_err(encode_error)
+}
+
+fn csv_cell(value: Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s,
+        Value::Array(items) if items.iter().all(|i| !i.is_array() && !i.is_object()) => items
+            .into_iter()
+            .map(csv_cell)
+            .collect::<Vec<String>>()
+            .join(";"),
+        other => other.to_string(),
+    }
+}
+
+// Balances are written as strings formatted by BalanceFormatter,
+// raw u128 amounts do not fit json numbers either
+
+#[derive(Serialize)]
+pub struct BlockRecord {
+    pub hash: String,
+    pub number: BlockNumber,
+}
+
+#[derive(Serialize)]
+pub struct PlannedEraRecord {
+    pub planned_era: EraIndex,
+}
+
+#[derive(Serialize)]
+pub struct SessionIndexRecord {
+    pub session_index: SessionIndex,
+}
+
+#[derive(Serialize)]
+pub struct ActiveEraRecord {
+    pub index: EraIndex,
+    /// Milliseconds since `$UNIX_EPOCH`
+    pub start: Option<u64>,
+}
+
+impl From<&ActiveEraInfo> for ActiveEraRecord {
+    fn from(info: &ActiveEraInfo) -> Self {
+        Self {
+            index: info.index,
+            start: info.start,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct StashRecord {
+    pub stash: String,
+}
+
+#[derive(Serialize)]
+pub struct NominatorRecord {
+    pub stash: String,
+    pub staked: String,
+    pub targets: Vec<String>,
+    pub submitted_in: EraIndex,
+    pub suppressed: bool,
+}
+
+impl NominatorRecord {
+    pub fn new(stash: &str, n: &Nominator, fmt: &BalanceFormatter) -> Self {
+        Self {
+            stash: stash.to_string(),
+            staked: fmt.format(n.staked),
+            targets: n
+                .nominations
+                .targets
+                .iter()
+                .map(|t| t.to_ss58check())
+                .collect(),
+            submitted_in: n.nominations.submitted_in,
+            suppressed: n.nominations.suppressed,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct WaitingValidatorRecord {
+    pub stash: String,
+    pub staked: String,
+    /// `None` when the stash has no ledger
+    pub claimed_rewards: Option<Vec<EraIndex>>,
+    pub nominators: Vec<String>,
+    pub commission_percent: f64,
+}
+
+impl WaitingValidatorRecord {
+    pub fn new(stash: &str, v: &WaitingValidator, fmt: &BalanceFormatter) -> Self {
+        Self {
+            stash: stash.to_string(),
+            staked: fmt.format(v.staked),
+            claimed_rewards: v.ledger.as_ref().map(|l| l.claimed_rewards.clone()),
+            nominators: v.nominators.clone(),
+            commission_percent: commission_percent(v.prefs.commission),
+        }
+    }
+}
+
+pub fn commission_percent(commission: Perbill) -> f64 {
+    commission.deconstruct() as f64 / 10_000_000.0
+}
+
+/// One change between two blocks
+///
+/// For `targets` changes, `from` holds the dropped targets and `to` the new ones.
+#[derive(Serialize)]
+pub struct ChangeRecord {
+    pub change: String,
+    pub stash: String,
+    pub from: String,
+    pub to: String,
+}
+
+impl ChangeRecord {
+    pub fn from_diff(d: &StakingDiff, fmt: &BalanceFormatter) -> Vec<Self> {
+        let record = |change: &str, stash: &str, from: String, to: String| Self {
+            change: change.to_string(),
+            stash: stash.to_string(),
+            from,
+            to,
+        };
+        let mut records = Vec::new();
+        for s in &d.validators_entered {
+            records.push(record("validator entered", s, String::new(), String::new()));
+        }
+        for s in &d.validators_left {
+            records.push(record("validator left", s, String::new(), String::new()));
+        }
+        for s in &d.nominators_added {
+            records.push(record("nominator added", s, String::new(), String::new()));
+        }
+        for s in &d.nominators_removed {
+            records.push(record("nominator removed", s, String::new(), String::new()));
+        }
+        let join = |accounts: &[AccountId]| {
+            accounts
+                .iter()
+                .map(|a| a.to_ss58check())
+                .collect::<Vec<String>>()
+                .join(";")
+        };
+        for t in &d.targets_changed {
+            records.push(record(
+                "targets",
+                &t.stash,
+                join(&t.removed),
+                join(&t.added),
+            ));
+        }
+        for s in &d.stake_changes {
+            records.push(record(
+                "stake",
+                &s.stash,
+                fmt.format(s.from),
+                fmt.format(s.to),
+            ));
+        }
+        for c in &d.commission_changes {
+            records.push(record(
+                "commission",
+                &c.stash,
+                format!("{}%", commission_percent(c.from)),
+                format!("{}%", commission_percent(c.to)),
+            ));
+        }
+        records
+    }
+}
+
+#[derive(Serialize)]
+pub struct ValidatorPointsRecord {
+    pub rank: usize,
+    pub stash: String,
+    pub points: RewardPoint,
+    /// Share of the era's points
+    pub percent: f64,
+    /// One of our own stashes
+    pub watched: bool,
+}
+
+#[derive(Serialize)]
+pub struct RewardPointsRecord {
+    pub era: EraIndex,
+    pub total: RewardPoint,
+    pub validators: Vec<ValidatorPointsRecord>,
+}
+
+impl RewardPointsRecord {
+    pub fn new(era: EraIndex, points: &EraRewardPoints<AccountId>, watched: &[AccountId]) -> Self {
+        let validators = points
+            .ranked()
+            .into_iter()
+            .enumerate()
+            .map(|(i, (stash, p))| ValidatorPointsRecord {
+                rank: i + 1,
+                stash: stash.to_ss58check(),
+                points: p,
+                percent: if points.total == 0 {
+                    0.0
+                } else {
+                    (p as f64 * 10_000.0 / points.total as f64).round() / 100.0
+                },
+                watched: watched.contains(stash),
+            })
+            .collect();
+        Self {
+            era,
+            total: points.total,
+            validators,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct UnclaimedPayoutRecord {
+    pub era: EraIndex,
+    pub validator: String,
+    /// Estimated
+    pub payout: String,
+}
+
+impl UnclaimedPayoutRecord {
+    pub fn new(p: &UnclaimedPayout, fmt: &BalanceFormatter) -> Self {
+        Self {
+            era: p.era,
+            validator: p.validator.to_ss58check(),
+            payout: fmt.format(p.payout),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct FeeRecord {
+    pub call: String,
+    pub weight: u64,
+    pub class: String,
+    pub partial_fee: String,
+}
+
+impl FeeRecord {
+    pub fn new(call: &str, fee: &FeeInfo, fmt: &BalanceFormatter) -> Self {
+        Self {
+            call: call.to_string(),
+            weight: fee.weight,
+            class: fee.class.clone(),
+            partial_fee: fmt.format(fee.partial_fee),
+        }
+    }
+}
+
+/// A keystore key, the account in the configured ss58 format
+#[derive(Serialize)]
+pub struct KeyRecord {
+    pub name: String,
+    pub account: String,
+    pub crypto: String,
+}
+
+impl KeyRecord {
+    pub fn new(entry: &KeyEntry) -> Self {
+        Self {
+            name: entry.name.clone(),
+            account: entry.account.to_ss58check(),
+            crypto: entry.crypto.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct IndividualExposureRecord {
+    pub who: String,
+    pub value: String,
+}
+
+#[derive(Serialize)]
+pub struct ExposureRecord {
+    pub stash: String,
+    pub total: String,
+    pub own: String,
+    pub others: Vec<IndividualExposureRecord>,
+}
+
+impl ExposureRecord {
+    /// Csv header, for when there are no exposures to write
+    pub const COLUMNS: &'static [&'static str] = &["stash", "total", "own", "others"];
+
+    pub fn new(
+        stash: &AccountId,
+        exposure: &Exposure<AccountId, Balance>,
+        fmt: &BalanceFormatter,
+    ) -> Self {
+        Self {
+            stash: stash.to_ss58check(),
+            total: fmt.format(exposure.total),
+            own: fmt.format(exposure.own),
+            others: exposure
+                .others
+                .iter()
+                .map(|o| IndividualExposureRecord {
+                    who: o.who.to_ss58check(),
+                    value: fmt.format(o.value),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ElectionResultRecord {
+    pub elected_stashes: Vec<String>,
+    pub exposures: Vec<ExposureRecord>,
+    pub compute: String,
+}
+
+impl ElectionResultRecord {
+    pub fn new(r: &ElectionResult<AccountId, Balance>, fmt: &BalanceFormatter) -> Self {
+        Self {
+            elected_stashes: r.elected_stashes.iter().map(|s| s.to_ss58check()).collect(),
+            exposures: r
+                .exposures
+                .iter()
+                .map(|(stash, exposure)| ExposureRecord::new(stash, exposure, fmt))
+                .collect(),
+            compute: format!("{:?}", r.compute),
+        }
+    }
+}
@@ -0,0 +1,284 @@
+//! Derived data built from raw storage reads, shared by ApiFilling and AsyncApiFilling
+
+use crate::{
+    storage, AccountId, Balance, DumplingError, EraRewardPoints, Nominator, Result, Ss58Codec,
+    StorageKey, UnclaimedPayout, WaitingValidator,
+};
+use balances::BalanceLock;
+use sp_runtime::Perbill;
+use staking::{EraIndex, Exposure, Nominations, StakingLedger, ValidatorPrefs, MAX_NOMINATIONS};
+use std::collections::HashMap;
+
+/// Nominators with the storage key they were read from
+pub(crate) type NominatorList = Vec<(StorageKey, AccountId, Option<Nominations<AccountId>>)>;
+
+pub(crate) fn nominator_list(
+    storage_keys: Vec<StorageKey>,
+    noms: Vec<Option<Nominations<AccountId>>>,
+) -> Result<NominatorList> {
+    let mut nom_list = Vec::new();
+    for (storage_key, nom) in storage_keys.into_iter().zip(noms) {
+        let account_id = storage::account_from_key(&storage_key)?;
+        nom_list.push((storage_key, account_id, nom));
+    }
+    Ok(nom_list)
+}
+
+pub(crate) fn nominators_by_validator(nom_list: NominatorList) -> HashMap<AccountId, Vec<String>> {
+    let mut v: HashMap<AccountId, Vec<String>> = HashMap::new();
+    for n in nom_list {
+        let nom_id = n.1.to_ss58check();
+        let nominations = n.2;
+        if let Some(n) = nominations {
+            for t in n.targets {
+                if let Some(nom_vec) = v.get_mut(&t) {
+                    nom_vec.push(nom_id.clone());
+                } else {
+                    v.insert(t, vec![nom_id.clone()]);
+                }
+            }
+        }
+    }
+    v
+}
+
+pub(crate) fn nominator_map(
+    nom_list: NominatorList,
+    staked: Vec<Balance>,
+) -> HashMap<String, Option<Nominator>> {
+    let mut nominations = HashMap::new();
+    for (n, staked) in nom_list.into_iter().zip(staked) {
+        let account_id = n.1;
+        let nom = n.2;
+        if let Some(n) = nom {
+            nominations.insert(
+                account_id.to_ss58check(),
+                Some(Nominator {
+                    nominations: n,
+                    staked: staked,
+                }),
+            );
+        } else {
+            nominations.insert(account_id.to_ss58check(), None);
+        };
+    }
+    nominations
+}
+
+pub(crate) fn waitlist(
+    stashes: Vec<AccountId>,
+    prefs: Vec<Option<ValidatorPrefs>>,
+    staked: Vec<Balance>,
+    ledgers: Vec<Option<StakingLedger<AccountId, Balance>>>,
+    v_to_n: &HashMap<AccountId, Vec<String>>,
+) -> Result<HashMap<String, WaitingValidator>> {
+    let mut waitlist = HashMap::new();
+    for (((account_id, prefs), staked), ledger) in
+        stashes.into_iter().zip(prefs).zip(staked).zip(ledgers)
+    {
+        let prefs = prefs.ok_or_else(|| {
+            DumplingError::MissingStorage(format!(
+                "Staking::Validators of {}",
+                account_id.to_ss58check()
+            ))
+        })?;
+        let nominators = v_to_n.get(&account_id).cloned().unwrap_or_default();
+
+        waitlist.insert(
+            account_id.to_ss58check(),
+            WaitingValidator {
+                staked: staked,
+                prefs: prefs,
+                nominators: nominators,
+                ledger: ledger,
+            },
+        );
+    }
+    Ok(waitlist)
+}
+
+/// `Staking::Ledger` keys of the stashes' controllers, the stash itself when not bonded
+pub(crate) fn ledger_keys(
+    stashes: &[AccountId],
+    controllers: &[Option<AccountId>],
+) -> Vec<StorageKey> {
+    stashes
+        .iter()
+        .zip(controllers)
+        .map(|(stash, controller)| storage::ledger_key(controller.as_ref().unwrap_or(stash)))
+        .collect()
+}
+
+/// Amount locked by staking, accounts without locks have nothing staked
+pub(crate) fn staking_lock(locks: &[BalanceLock<Balance>]) -> Balance {
+    let mut staked: Balance = 0;
+    for i in locks {
+        if i.id == *b"staking " {
+            staked = i.amount;
+        }
+    }
+    staked
+}
+
+/// How a stash took part in the eras scanned for unclaimed rewards
+pub(crate) enum Staker {
+    /// Rewarded in at least one of the eras
+    Validator,
+    /// With its current nominations, if any
+    Nominator(Option<Nominations<AccountId>>),
+}
+
+impl Staker {
+    /// Exposures a stash can be in per era
+    pub(crate) fn max_exposures(&self) -> usize {
+        match self {
+            Staker::Validator => 1,
+            Staker::Nominator(_) => MAX_NOMINATIONS,
+        }
+    }
+}
+
+/// Whether `stash` earned points in any of the eras
+pub(crate) fn validated(stash: &AccountId, points: &[Option<EraRewardPoints<AccountId>>]) -> bool {
+    points
+        .iter()
+        .flatten()
+        .any(|p| p.individual.contains_key(stash))
+}
+
+/// Rewarded validators `stash` may have been exposed to, for each of `eras`
+///
+/// A validator is only exposed to itself. A nominator to its current targets once they were
+/// elected, and to any rewarded validator before that, so former targets are found too.
+pub(crate) fn exposure_candidates<'a>(
+    stash: &AccountId,
+    staker: &Staker,
+    eras: &[EraIndex],
+    points: &'a [Option<EraRewardPoints<AccountId>>],
+) -> Vec<Vec<&'a AccountId>> {
+    eras.iter()
+        .zip(points)
+        .map(|(era, points)| {
+            let rewarded = points.iter().flat_map(|p| p.individual.keys());
+            match staker {
+                Staker::Validator => rewarded.filter(|v| *v == stash).collect(),
+                // Targets are elected at the end of the era after they were submitted
+                Staker::Nominator(Some(n)) if n.submitted_in + 1 < *era => {
+                    rewarded.filter(|v| n.targets.contains(*v)).collect()
+                }
+                Staker::Nominator(_) => rewarded.collect(),
+            }
+        })
+        .collect()
+}
+
+/// Validators of `candidates`, each once, whose ledgers tell the eras they claimed
+pub(crate) fn candidate_validators(candidates: &[Vec<&AccountId>]) -> Vec<AccountId> {
+    let mut validators: Vec<AccountId> =
+        candidates.iter().flatten().map(|v| (*v).clone()).collect();
+    validators.sort();
+    validators.dedup();
+    validators
+}
+
+/// Eras claimed by each of `validators`, those without a ledger cannot be paid out
+pub(crate) fn claimed_eras(
+    validators: &[AccountId],
+    ledgers: Vec<Option<StakingLedger<AccountId, Balance>>>,
+) -> HashMap<&AccountId, Vec<EraIndex>> {
+    validators
+        .iter()
+        .zip(ledgers)
+        .filter_map(|(v, l)| Some((v, l?.claimed_rewards)))
+        .collect()
+}
+
+/// Candidates of `era` that did not claim it yet
+pub(crate) fn pending<'a>(
+    era: EraIndex,
+    candidates: Vec<&'a AccountId>,
+    claimed: &HashMap<&AccountId, Vec<EraIndex>>,
+) -> Vec<&'a AccountId> {
+    candidates
+        .into_iter()
+        .filter(|v| match claimed.get(v) {
+            Some(claimed) => !claimed.contains(&era),
+            None => false,
+        })
+        .collect()
+}
+
+/// Whether `stash` is paid out from the exposure of `validator`
+pub(crate) fn exposed_to(
+    stash: &AccountId,
+    validator: &AccountId,
+    exposure: &Exposure<AccountId, Balance>,
+) -> bool {
+    exposure.total > 0 && (validator == stash || exposure.others.iter().any(|o| &o.who == stash))
+}
+
+/// Estimated payouts of `stash` from the `(era, validator, exposure)` it was found in, by era
+/// then validator
+///
+/// `prefs`, `era_rewards` and `points` are in the order of `exposed` and of the eras from
+/// `first_era`.
+pub(crate) fn unclaimed_payouts(
+    stash: &AccountId,
+    first_era: EraIndex,
+    exposed: Vec<(EraIndex, AccountId, Exposure<AccountId, Balance>)>,
+    prefs: Vec<Option<ValidatorPrefs>>,
+    era_rewards: &[Option<Balance>],
+    points: &[Option<EraRewardPoints<AccountId>>],
+) -> Vec<UnclaimedPayout> {
+    let mut unclaimed = Vec::new();
+    for ((era, validator, exposure), prefs) in exposed.into_iter().zip(prefs) {
+        let i = (era - first_era) as usize;
+        let (era_reward, points) = match (&era_rewards[i], &points[i]) {
+            (Some(r), Some(p)) => (*r, p),
+            _ => continue,
+        };
+        let commission = prefs.map(|p| p.commission).unwrap_or_default();
+        if let Some(payout) =
+            estimate_payout(stash, &validator, &exposure, commission, era_reward, points)
+        {
+            unclaimed.push(UnclaimedPayout {
+                era,
+                validator,
+                payout,
+            });
+        }
+    }
+    unclaimed.sort_by(|a, b| {
+        a.era
+            .cmp(&b.era)
+            .then_with(|| a.validator.cmp(&b.validator))
+    });
+    unclaimed
+}
+
+/// Share of `stash` in the era reward of `validator`, as `payout_stakers` splits it
+///
+/// `None` when the validator earned no points or `stash` is not in its exposure.
+pub(crate) fn estimate_payout(
+    stash: &AccountId,
+    validator: &AccountId,
+    exposure: &Exposure<AccountId, Balance>,
+    commission: Perbill,
+    era_reward: Balance,
+    points: &EraRewardPoints<AccountId>,
+) -> Option<Balance> {
+    let validator_points = *points.individual.get(validator)?;
+    if validator_points == 0 || points.total == 0 {
+        return None;
+    }
+    let total = Perbill::from_rational_approximation(validator_points, points.total) * era_reward;
+    let commission_payout = commission * total;
+    let leftover = total - commission_payout;
+    if stash == validator {
+        let own = Perbill::from_rational_approximation(exposure.own, exposure.total) * leftover;
+        Some(commission_payout + own)
+    } else {
+        let value = exposure.others.iter().find(|o| &o.who == stash)?.value;
+        Some(Perbill::from_rational_approximation(value, exposure.total) * leftover)
+    }
+}
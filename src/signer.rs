@@ -0,0 +1,91 @@
+//! Keys signing extrinsics
+
+use crate::{ed25519, sr25519, AccountId, DumplingError, MultiSignature, Pair, Result};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Signature schemes accounts can sign with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CryptoType {
+    Sr25519,
+    Ed25519,
+}
+
+impl FromStr for CryptoType {
+    type Err = DumplingError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sr25519" => Ok(CryptoType::Sr25519),
+            "ed25519" => Ok(CryptoType::Ed25519),
+            _ => Err(DumplingError::InvalidKey(format!(
+                "crypto {}, use sr25519 or ed25519",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for CryptoType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CryptoType::Sr25519 => write!(f, "sr25519"),
+            CryptoType::Ed25519 => write!(f, "ed25519"),
+        }
+    }
+}
+
+/// Key pair set as the signer of the node api
+#[derive(Clone)]
+pub enum Signer {
+    Sr25519(sr25519::Pair),
+    Ed25519(ed25519::Pair),
+}
+
+impl Signer {
+    /// Key from a secret uri: a mnemonic phrase, a `0x` prefixed seed or a derivation
+    /// such as `//Alice`
+    pub fn from_uri(uri: &str, crypto: CryptoType) -> Result<Self> {
+        let invalid = |_| DumplingError::InvalidKey("secret uri".to_string());
+        Ok(match crypto {
+            CryptoType::Sr25519 => {
+                Signer::Sr25519(sr25519::Pair::from_string(uri, None).map_err(invalid)?)
+            }
+            CryptoType::Ed25519 => {
+                Signer::Ed25519(ed25519::Pair::from_string(uri, None).map_err(invalid)?)
+            }
+        })
+    }
+
+    pub fn crypto(&self) -> CryptoType {
+        match self {
+            Signer::Sr25519(_) => CryptoType::Sr25519,
+            Signer::Ed25519(_) => CryptoType::Ed25519,
+        }
+    }
+
+    /// Account signing with the key
+    pub fn account(&self) -> AccountId {
+        match self {
+            Signer::Sr25519(pair) => pair.public().into(),
+            Signer::Ed25519(pair) => pair.public().into(),
+        }
+    }
+
+    pub fn sign(&self, message: &[u8]) -> MultiSignature {
+        match self {
+            Signer::Sr25519(pair) => pair.sign(message).into(),
+            Signer::Ed25519(pair) => pair.sign(message).into(),
+        }
+    }
+}
+
+/// Reads an sr25519 key from a file holding a secret uri, see Signer::from_uri
+pub fn signer_from_file(path: &Path) -> Result<Signer> {
+    let secret = fs::read_to_string(path)
+        .map_err(|e| DumplingError::Io(format!("{}: {}", path.display(), e)))?;
+    Signer::from_uri(secret.trim(), CryptoType::Sr25519)
+        .map_err(|_| DumplingError::InvalidKey(format!("secret in {}", path.display())))
+}
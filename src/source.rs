@@ -0,0 +1,323 @@
+//! Backends ApiFilling reads chain state from
+
+use crate::{
+    sr25519, storage, BlakeTwo256, BlockNumber, DumplingError, Hash, Header, Result, StorageKey,
+};
+use serde_json::{json, Value};
+use std::panic;
+use std::sync::mpsc::Sender;
+use std::thread;
+use substrate_api_client::Api;
+
+/// Raw access to the state of a chain
+///
+/// Keys and values are SCALE encoded as they are in storage, decoding is left to ApiFilling.
+/// `None` as block hash is the latest block of the source.
+pub trait StorageSource {
+    /// Raw storage value, or map entry, at `key`
+    fn storage(&self, key: &StorageKey, block_hash: Option<Hash>) -> Result<Option<Vec<u8>>>;
+
+    /// Raw values of `keys`, in order, read in a single request where the backend allows it
+    fn storage_batch(
+        &self,
+        keys: &[StorageKey],
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        keys.iter().map(|k| self.storage(k, block_hash)).collect()
+    }
+
+    /// One page of at most `count` keys under `prefix`, starting after `start_key`
+    fn keys_paged(
+        &self,
+        prefix: &StorageKey,
+        count: u32,
+        start_key: Option<&StorageKey>,
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<StorageKey>>;
+
+    fn finalized_head(&self) -> Result<Hash>;
+
+    fn header(&self, hash: Hash) -> Result<Header<BlockNumber, BlakeTwo256>>;
+
+    /// Hash of the block at `number`, the latest block if none is given
+    fn block_hash(&self, number: Option<BlockNumber>) -> Result<Hash>;
+
+    /// Raw json-rpc call returning the `result` field, only node backends support it
+    fn rpc(&self, method: &str, _params: Value) -> Result<Value> {
+        Err(DumplingError::Unsupported(format!(
+            "{} without a node connection",
+            method
+        )))
+    }
+
+    /// The node behind the source, needed to sign and submit extrinsics
+    fn node(&self) -> Option<&NodeSource> {
+        None
+    }
+
+    /// Sends every new finalized header, as json, until the receiver is dropped
+    fn subscribe_finalized_heads(&self, _sender: Sender<String>) -> Result<()> {
+        Err(DumplingError::Unsupported(
+            "chain_subscribeFinalizedHeads without a node connection".to_string(),
+        ))
+    }
+
+    /// Submits a signed extrinsic and sends every status update of the transaction pool,
+    /// as json, until the last one or until the receiver is dropped
+    ///
+    /// A rejected submission is sent as `{"error": ...}`.
+    fn submit_and_watch(&self, _extrinsic: String, _sender: Sender<String>) -> Result<()> {
+        Err(DumplingError::Unsupported(
+            "author_submitAndWatchExtrinsic without a node connection".to_string(),
+        ))
+    }
+}
+
+impl<S: StorageSource + ?Sized> StorageSource for Box<S> {
+    fn storage(&self, key: &StorageKey, block_hash: Option<Hash>) -> Result<Option<Vec<u8>>> {
+        (**self).storage(key, block_hash)
+    }
+
+    fn storage_batch(
+        &self,
+        keys: &[StorageKey],
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        (**self).storage_batch(keys, block_hash)
+    }
+
+    fn keys_paged(
+        &self,
+        prefix: &StorageKey,
+        count: u32,
+        start_key: Option<&StorageKey>,
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<StorageKey>> {
+        (**self).keys_paged(prefix, count, start_key, block_hash)
+    }
+
+    fn finalized_head(&self) -> Result<Hash> {
+        (**self).finalized_head()
+    }
+
+    fn header(&self, hash: Hash) -> Result<Header<BlockNumber, BlakeTwo256>> {
+        (**self).header(hash)
+    }
+
+    fn block_hash(&self, number: Option<BlockNumber>) -> Result<Hash> {
+        (**self).block_hash(number)
+    }
+
+    fn rpc(&self, method: &str, params: Value) -> Result<Value> {
+        (**self).rpc(method, params)
+    }
+
+    fn node(&self) -> Option<&NodeSource> {
+        (**self).node()
+    }
+
+    fn subscribe_finalized_heads(&self, sender: Sender<String>) -> Result<()> {
+        (**self).subscribe_finalized_heads(sender)
+    }
+
+    fn submit_and_watch(&self, extrinsic: String, sender: Sender<String>) -> Result<()> {
+        (**self).submit_and_watch(extrinsic, sender)
+    }
+}
+
+/// Keeps the scheme of the given url, falling back to `ws://` when there is none
+pub(crate) fn endpoint(url: &str) -> String {
+    if url.contains("://") {
+        url.to_string()
+    } else {
+        format!("ws://{}", url)
+    }
+}
+
+/// A node reached through substrate-api-client
+pub struct NodeSource {
+    pub api: Api<sr25519::Pair>,
+}
+
+impl NodeSource {
+    /// Connects to `url`, see ApiFilling::new
+    pub fn new(url: &str) -> Result<Self> {
+        let endpoint = endpoint(url);
+        // substrate-api-client panics when it cannot fetch metadata from the node
+        let api = panic::catch_unwind(|| Api::<sr25519::Pair>::new(endpoint.clone()))
+            .map_err(|_| DumplingError::Connection(format!("cannot connect to {}", endpoint)))?;
+        Ok(Self { api })
+    }
+}
+
+impl StorageSource for NodeSource {
+    fn storage(&self, key: &StorageKey, block_hash: Option<Hash>) -> Result<Option<Vec<u8>>> {
+        match self.rpc(
+            "state_getStorage",
+            json!([storage::to_hex(&key.0), storage::at_param(block_hash)]),
+        )? {
+            Value::String(value) => storage::from_hex(&value).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// One `state_queryStorageAt` request for all of `keys`
+    fn storage_batch(
+        &self,
+        keys: &[StorageKey],
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        let hex_keys: Vec<String> = keys.iter().map(|k| storage::to_hex(&k.0)).collect();
+        let response = self.rpc(
+            "state_queryStorageAt",
+            json!([hex_keys, storage::at_param(block_hash)]),
+        )?;
+        storage::query_storage_values(keys, &response)
+    }
+
+    fn keys_paged(
+        &self,
+        prefix: &StorageKey,
+        count: u32,
+        start_key: Option<&StorageKey>,
+        block_hash: Option<Hash>,
+    ) -> Result<Vec<StorageKey>> {
+        let start_key = match start_key {
+            Some(k) => Value::String(storage::to_hex(&k.0)),
+            None => Value::Null,
+        };
+        let response = self.rpc(
+            "state_getKeysPaged",
+            json!([
+                storage::to_hex(&prefix.0),
+                count,
+                start_key,
+                storage::at_param(block_hash)
+            ]),
+        )?;
+        storage::keys_from_response(&response)
+    }
+
+    fn finalized_head(&self) -> Result<Hash> {
+        self.api.get_finalized_head().ok_or_else(|| {
+            DumplingError::Connection("chain_getFinalizedHead returned nothing".to_string())
+        })
+    }
+
+    fn header(&self, hash: Hash) -> Result<Header<BlockNumber, BlakeTwo256>> {
+        self.api
+            .get_header(Some(hash))
+            .ok_or_else(|| DumplingError::MissingStorage(format!("Header of {:?}", hash)))
+    }
+
+    fn block_hash(&self, number: Option<BlockNumber>) -> Result<Hash> {
+        let params = match number {
+            Some(n) => json!([n]),
+            None => json!([]),
+        };
+        match self.rpc("chain_getBlockHash", params)? {
+            Value::String(hash) => storage::parse_hash(&hash),
+            _ => Err(match number {
+                Some(n) => DumplingError::MissingStorage(format!("Block #{}", n)),
+                None => {
+                    DumplingError::Connection("chain_getBlockHash returned nothing".to_string())
+                }
+            }),
+        }
+    }
+
+    fn rpc(&self, method: &str, params: Value) -> Result<Value> {
+        let req = json!({
+            "method": method,
+            "params": params,
+            "jsonrpc": "2.0",
+            "id": "1",
+        });
+        let res = self
+            .api
+            .get_request(req.to_string())
+            .ok_or_else(|| DumplingError::Connection(format!("{} returned nothing", method)))?;
+        serde_json::from_str(&res)
+            .map_err(|_| DumplingError::Decode(format!("{} response: {}", method, res)))
+    }
+
+    fn node(&self) -> Option<&NodeSource> {
+        Some(self)
+    }
+
+    /// substrate-api-client reads the subscription on its own thread
+    fn subscribe_finalized_heads(&self, sender: Sender<String>) -> Result<()> {
+        self.api.subscribe_finalized_heads(sender);
+        Ok(())
+    }
+
+    /// substrate-api-client only returns the final block hash of an extrinsic, the
+    /// subscription is read on a websocket of its own instead
+    fn submit_and_watch(&self, extrinsic: String, sender: Sender<String>) -> Result<()> {
+        let url = self.api.url.clone();
+        let request = json!({
+            "method": "author_submitAndWatchExtrinsic",
+            "params": [extrinsic],
+            "jsonrpc": "2.0",
+            "id": "1",
+        })
+        .to_string();
+        thread::spawn(move || {
+            let result = ws::connect(url, |out| ExtrinsicWatcher {
+                out,
+                request: request.clone(),
+                sender: sender.clone(),
+            });
+            if let Err(e) = result {
+                let _ = sender.send(json!({ "error": e.to_string() }).to_string());
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Forwards the updates of an `author_submitAndWatchExtrinsic` subscription
+struct ExtrinsicWatcher {
+    out: ws::Sender,
+    request: String,
+    sender: Sender<String>,
+}
+
+impl ExtrinsicWatcher {
+    /// Statuses after which the pool sends nothing more about the extrinsic
+    fn is_last(update: &Value) -> bool {
+        match update {
+            Value::String(status) => status == "dropped" || status == "invalid",
+            Value::Object(status) => {
+                status.contains_key("finalized")
+                    || status.contains_key("usurped")
+                    || status.contains_key("finalityTimeout")
+            }
+            _ => true,
+        }
+    }
+}
+
+impl ws::Handler for ExtrinsicWatcher {
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        self.out.send(self.request.clone())
+    }
+
+    fn on_message(&mut self, msg: ws::Message) -> ws::Result<()> {
+        let msg: Value = serde_json::from_str(msg.as_text()?).unwrap_or(Value::Null);
+        let (update, last) = if msg.get("error").is_some() {
+            (json!({ "error": msg["error"] }), true)
+        } else if msg["method"] == "author_extrinsicUpdate" {
+            let update = msg["params"]["result"].clone();
+            let last = Self::is_last(&update);
+            (update, last)
+        } else {
+            // The subscription id
+            return Ok(());
+        };
+        if self.sender.send(update.to_string()).is_err() || last {
+            self.out.close(ws::CloseCode::Normal)?;
+        }
+        Ok(())
+    }
+}
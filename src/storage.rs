@@ -0,0 +1,225 @@
+use crate::{sr25519, AccountId, DumplingError, Hash, Public, Result, StorageKey};
+use codec::{Decode, Encode};
+use hex::FromHex;
+use serde_json::Value;
+use sp_core::hashing::{blake2_128, twox_128, twox_64};
+use sp_staking::SessionIndex;
+use staking::EraIndex;
+
+/// Hashers of the storage maps dumpling reads
+///
+/// | Map                                          | Hasher            |
+/// |----------------------------------------------|-------------------|
+/// | System::Account                              | Blake2_128Concat  |
+/// | Balances::Locks, Staking::Ledger             | Blake2_128Concat  |
+/// | Staking::Bonded, Validators, Nominators      | Twox64Concat      |
+/// | Staking::Eras* (era and stash keys)          | Twox64Concat      |
+/// | ImOnline::ReceivedHeartbeats, AuthoredBlocks | Twox64Concat      |
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hasher {
+    Twox64Concat,
+    Blake2_128Concat,
+}
+
+impl Hasher {
+    pub fn hash(&self, encoded: &[u8]) -> Vec<u8> {
+        let mut hashed = match self {
+            Hasher::Twox64Concat => twox_64(encoded).to_vec(),
+            Hasher::Blake2_128Concat => blake2_128(encoded).to_vec(),
+        };
+        hashed.extend_from_slice(encoded);
+        hashed
+    }
+}
+
+/// Key of a storage value, also the prefix of every entry of a storage map
+pub fn value_key(module: &str, item: &str) -> StorageKey {
+    let mut key = twox_128(module.as_bytes()).to_vec();
+    key.extend_from_slice(&twox_128(item.as_bytes()));
+    StorageKey(key)
+}
+
+pub fn map_key<K: Encode>(module: &str, item: &str, hasher: Hasher, key: &K) -> StorageKey {
+    let mut k = value_key(module, item).0;
+    k.extend(hasher.hash(&key.encode()));
+    StorageKey(k)
+}
+
+pub fn double_map_key<K1: Encode, K2: Encode>(
+    module: &str,
+    item: &str,
+    key1: (Hasher, &K1),
+    key2: (Hasher, &K2),
+) -> StorageKey {
+    let mut k = value_key(module, item).0;
+    k.extend(key1.0.hash(&key1.1.encode()));
+    k.extend(key2.0.hash(&key2.1.encode()));
+    StorageKey(k)
+}
+
+/// `0x` prefixed lower case hex
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(2 + bytes.len() * 2);
+    s.push_str("0x");
+    for b in bytes {
+        s.push_str(&format!("{:02x}", b));
+    }
+    s
+}
+
+pub fn from_hex(s: &str) -> Result<Vec<u8>> {
+    Vec::from_hex(s.trim_start_matches("0x"))
+        .map_err(|_| DumplingError::Decode(format!("hex {}", s)))
+}
+
+/// Block hash as an rpc parameter, `null` for the latest block
+pub fn at_param(at: Option<Hash>) -> Value {
+    match at {
+        Some(hash) => Value::String(to_hex(hash.as_bytes())),
+        None => Value::Null,
+    }
+}
+
+/// Values of `keys`, in order, from a `state_queryStorageAt` response
+pub fn query_storage_values(keys: &[StorageKey], response: &Value) -> Result<Vec<Option<Vec<u8>>>> {
+    let malformed = || DumplingError::Decode("state_queryStorageAt response".to_string());
+    let mut values = std::collections::HashMap::new();
+    for change_set in response.as_array().ok_or_else(malformed)? {
+        let changes = change_set
+            .get("changes")
+            .and_then(Value::as_array)
+            .ok_or_else(malformed)?;
+        for change in changes {
+            let key = change
+                .get(0)
+                .and_then(Value::as_str)
+                .ok_or_else(malformed)?;
+            let value = match change.get(1).and_then(Value::as_str) {
+                Some(v) => Some(from_hex(v)?),
+                None => None,
+            };
+            values.insert(from_hex(key)?, value);
+        }
+    }
+    Ok(keys
+        .iter()
+        .map(|k| values.get(&k.0).cloned().unwrap_or(None))
+        .collect())
+}
+
+/// Keys of a `state_getKeysPaged` response
+pub fn keys_from_response(response: &Value) -> Result<Vec<StorageKey>> {
+    response
+        .as_array()
+        .ok_or_else(|| DumplingError::Decode("state_getKeysPaged response".to_string()))?
+        .iter()
+        .map(|k| match k.as_str() {
+            Some(k) => from_hex(k).map(StorageKey),
+            None => Err(DumplingError::Decode(format!("storage key {}", k))),
+        })
+        .collect()
+}
+
+/// Decodes raw values fetched for `keys`, `None` for empty entries
+pub fn decode_values<V: Decode>(
+    keys: &[StorageKey],
+    values: Vec<Option<Vec<u8>>>,
+) -> Result<Vec<Option<V>>> {
+    values
+        .into_iter()
+        .zip(keys)
+        .map(|(value, key)| match value {
+            Some(bytes) => V::decode(&mut &bytes[..])
+                .map(Some)
+                .map_err(|_| DumplingError::Decode(format!("value of {}", to_hex(&key.0)))),
+            None => Ok(None),
+        })
+        .collect()
+}
+
+pub fn parse_hash(hash: &str) -> Result<Hash> {
+    match from_hex(hash) {
+        Ok(bytes) if bytes.len() == 32 => Ok(Hash::from_slice(&bytes)),
+        _ => Err(DumplingError::Decode(format!("block hash {}", hash))),
+    }
+}
+
+/// Account at the end of a `Twox64Concat` map key, e.g. the stash of `Staking::Validators`
+pub fn account_from_key(s: &StorageKey) -> Result<AccountId> {
+    if s.0.len() != 72 {
+        return Err(DumplingError::Decode(format!(
+            "account from storage key {}",
+            to_hex(&s.0)
+        )));
+    }
+    Ok(sr25519::Public::from_slice(&s.0[40..]).into())
+}
+
+pub fn account_key(account: &AccountId) -> StorageKey {
+    map_key("System", "Account", Hasher::Blake2_128Concat, account)
+}
+
+pub fn locks_key(account: &AccountId) -> StorageKey {
+    map_key("Balances", "Locks", Hasher::Blake2_128Concat, account)
+}
+
+pub fn bonded_key(stash: &AccountId) -> StorageKey {
+    map_key("Staking", "Bonded", Hasher::Twox64Concat, stash)
+}
+
+pub fn ledger_key(controller: &AccountId) -> StorageKey {
+    map_key("Staking", "Ledger", Hasher::Blake2_128Concat, controller)
+}
+
+pub fn nominators_key(stash: &AccountId) -> StorageKey {
+    map_key("Staking", "Nominators", Hasher::Twox64Concat, stash)
+}
+
+pub fn era_reward_key(era: EraIndex) -> StorageKey {
+    map_key("Staking", "ErasValidatorReward", Hasher::Twox64Concat, &era)
+}
+
+pub fn eras_stakers_clipped_key(era: EraIndex, stash: &AccountId) -> StorageKey {
+    double_map_key(
+        "Staking",
+        "ErasStakersClipped",
+        (Hasher::Twox64Concat, &era),
+        (Hasher::Twox64Concat, stash),
+    )
+}
+
+pub fn eras_validator_prefs_key(era: EraIndex, stash: &AccountId) -> StorageKey {
+    double_map_key(
+        "Staking",
+        "ErasValidatorPrefs",
+        (Hasher::Twox64Concat, &era),
+        (Hasher::Twox64Concat, stash),
+    )
+}
+
+pub fn reward_points_key(era: EraIndex) -> StorageKey {
+    map_key("Staking", "ErasRewardPoints", Hasher::Twox64Concat, &era)
+}
+
+pub fn validators_key(stash: &AccountId) -> StorageKey {
+    map_key("Staking", "Validators", Hasher::Twox64Concat, stash)
+}
+
+/// Heartbeat of the validator at `auth_index` of the session validators
+pub fn heartbeat_key(session_index: SessionIndex, auth_index: u32) -> StorageKey {
+    double_map_key(
+        "ImOnline",
+        "ReceivedHeartbeats",
+        (Hasher::Twox64Concat, &session_index),
+        (Hasher::Twox64Concat, &auth_index),
+    )
+}
+
+pub fn authored_blocks_key(session_index: SessionIndex, stash: &AccountId) -> StorageKey {
+    double_map_key(
+        "ImOnline",
+        "AuthoredBlocks",
+        (Hasher::Twox64Concat, &session_index),
+        (Hasher::Twox64Concat, stash),
+    )
+}
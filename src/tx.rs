@@ -0,0 +1,241 @@
+//! Extrinsics built with a node, signed offline and broadcast apart
+//!
+//! The signed extension data matches the polkadot, kusama and westend runtimes: the mortality,
+//! nonce and tip are part of the extrinsic, the spec and transaction versions, genesis hash
+//! and mortality checkpoint are only signed.
+
+use crate::{
+    calls::lookup, parse_account, signer::Signer, storage, AccountId, ApiFilling, Balance,
+    BlockNumber, DumplingError, Hash, Result, Ss58Codec, StorageSource,
+};
+use codec::{Compact, Decode, Encode};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sp_core::hashing::blake2_256;
+use sp_runtime::generic::Era;
+use std::fmt;
+use std::sync::mpsc::channel;
+
+/// Version 4 extrinsic, with the signed bit set
+const SIGNED_EXTRINSIC_V4: u8 = 0b1000_0100;
+
+/// Everything needed to sign an extrinsic without network access
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnsignedTx {
+    /// ss58 address of the account expected to sign
+    pub signer: String,
+    /// `0x` prefixed SCALE encoded call
+    pub call: String,
+    pub nonce: u32,
+    pub tip: Balance,
+    /// `0x` prefixed SCALE encoded mortality
+    pub era: String,
+    /// Checkpoint block of the mortality, the genesis hash when immortal
+    pub era_hash: Hash,
+    pub genesis_hash: Hash,
+    pub spec_version: u32,
+    /// Only signed by runtimes with a transaction version
+    pub transaction_version: Option<u32>,
+}
+
+/// How far a submitted extrinsic got, as the transaction pool reports it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TxProgress {
+    /// Waiting for an earlier nonce of the signer
+    Future,
+    Ready,
+    /// Gossiped to that many peers
+    Broadcast(usize),
+    InBlock(Hash),
+    /// The block including it left the best chain, it goes back to the pool
+    Retracted(Hash),
+    /// The block including it was not finalized in time
+    FinalityTimeout(Hash),
+    Finalized(Hash),
+    /// Replaced by another extrinsic with the same nonce, of that hash
+    Usurped(Hash),
+    Dropped,
+    Invalid,
+}
+
+impl TxProgress {
+    /// Reads an `author_extrinsicUpdate` of the `author_submitAndWatchExtrinsic` subscription
+    pub fn from_update(update: &Value) -> Option<Self> {
+        if let Value::String(status) = update {
+            return match status.as_str() {
+                "future" => Some(TxProgress::Future),
+                "ready" => Some(TxProgress::Ready),
+                "dropped" => Some(TxProgress::Dropped),
+                "invalid" => Some(TxProgress::Invalid),
+                _ => None,
+            };
+        }
+        let (status, value) = update.as_object()?.iter().next()?;
+        if status == "broadcast" {
+            return Some(TxProgress::Broadcast(value.as_array()?.len()));
+        }
+        let hash = storage::parse_hash(value.as_str()?).ok()?;
+        match status.as_str() {
+            "inBlock" => Some(TxProgress::InBlock(hash)),
+            "retracted" => Some(TxProgress::Retracted(hash)),
+            "finalityTimeout" => Some(TxProgress::FinalityTimeout(hash)),
+            "finalized" => Some(TxProgress::Finalized(hash)),
+            "usurped" => Some(TxProgress::Usurped(hash)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TxProgress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex = |hash: &Hash| storage::to_hex(hash.as_bytes());
+        match self {
+            TxProgress::Future => write!(f, "Future, waiting for an earlier nonce"),
+            TxProgress::Ready => write!(f, "Ready"),
+            TxProgress::Broadcast(peers) => write!(f, "Broadcast to {} peers", peers),
+            TxProgress::InBlock(block) => write!(f, "In block {}", hex(block)),
+            TxProgress::Retracted(block) => write!(f, "Retracted from block {}", hex(block)),
+            TxProgress::FinalityTimeout(block) => {
+                write!(f, "Block {} not finalized in time", hex(block))
+            }
+            TxProgress::Finalized(block) => write!(f, "Finalized in block {}", hex(block)),
+            TxProgress::Usurped(xt) => write!(f, "Usurped by extrinsic {}", hex(xt)),
+            TxProgress::Dropped => write!(f, "Dropped from the transaction pool"),
+            TxProgress::Invalid => write!(f, "Invalid"),
+        }
+    }
+}
+
+impl UnsignedTx {
+    /// Bytes the signer signs: call, extra and additional signed data,
+    /// hashed when longer than 256 bytes
+    pub fn signing_payload(&self) -> Result<Vec<u8>> {
+        let mut payload = storage::from_hex(&self.call)?;
+        payload.extend(self.extra()?);
+        self.spec_version.encode_to(&mut payload);
+        if let Some(version) = self.transaction_version {
+            version.encode_to(&mut payload);
+        }
+        self.genesis_hash.encode_to(&mut payload);
+        self.era_hash.encode_to(&mut payload);
+        if payload.len() > 256 {
+            return Ok(blake2_256(&payload).to_vec());
+        }
+        Ok(payload)
+    }
+
+    /// Signs without network access, returns the `0x` prefixed extrinsic
+    pub fn sign(&self, signer: &Signer) -> Result<String> {
+        let account = signer.account();
+        if account != parse_account(&self.signer)? {
+            return Err(DumplingError::InvalidKey(format!(
+                "{} signs, not {}",
+                self.signer,
+                account.to_ss58check()
+            )));
+        }
+        let signature = signer.sign(&self.signing_payload()?);
+
+        let mut extrinsic = vec![SIGNED_EXTRINSIC_V4];
+        lookup(&account).encode_to(&mut extrinsic);
+        signature.encode_to(&mut extrinsic);
+        extrinsic.extend(self.extra()?);
+        extrinsic.extend(storage::from_hex(&self.call)?);
+        // Extrinsics are length prefixed, as a Vec<u8>
+        Ok(storage::to_hex(&extrinsic.encode()))
+    }
+
+    /// Mortality, nonce and tip, as included in the extrinsic
+    fn extra(&self) -> Result<Vec<u8>> {
+        let era = Era::decode(&mut &storage::from_hex(&self.era)?[..])
+            .map_err(|_| DumplingError::Decode(format!("era {}", self.era)))?;
+        Ok((era, Compact(self.nonce), Compact(self.tip)).encode())
+    }
+}
+
+/// Hash identifying a `0x` prefixed extrinsic
+pub fn extrinsic_hash(extrinsic: &str) -> Result<Hash> {
+    Ok(Hash::from(blake2_256(&storage::from_hex(extrinsic)?)))
+}
+
+impl<S: StorageSource> ApiFilling<S> {
+    /// Unsigned extrinsic of `signer` for `call`, valid for `mortal_for` blocks
+    /// from the latest finalized one, forever if zero
+    pub fn build_tx(&self, signer: &AccountId, call: &[u8], mortal_for: u64) -> Result<UnsignedTx> {
+        let nonce = self
+            .rpc("system_accountNextIndex", json!([signer.to_ss58check()]))?
+            .as_u64()
+            .ok_or_else(|| DumplingError::Decode("system_accountNextIndex response".to_string()))?;
+        let version = self.rpc("state_getRuntimeVersion", json!([]))?;
+        let spec_version = version
+            .get("specVersion")
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| DumplingError::Decode(format!("runtime version {}", version)))?;
+        let transaction_version = version
+            .get("transactionVersion")
+            .and_then(|v| v.as_u64())
+            .map(|v| v as u32);
+
+        let genesis_hash = self.block_hash(0)?;
+        let (era, era_hash) = if mortal_for == 0 {
+            (Era::Immortal, genesis_hash)
+        } else {
+            let (_, head) = self.finalized_head()?;
+            let era = Era::mortal(mortal_for, head.number as u64);
+            let birth = era.birth(head.number as u64) as BlockNumber;
+            (era, self.block_hash(birth)?)
+        };
+        Ok(UnsignedTx {
+            signer: signer.to_ss58check(),
+            call: storage::to_hex(call),
+            nonce: nonce as u32,
+            tip: 0,
+            era: storage::to_hex(&era.encode()),
+            era_hash,
+            genesis_hash,
+            spec_version: spec_version as u32,
+            transaction_version,
+        })
+    }
+
+    /// Submits a signed extrinsic with `author_submitAndWatchExtrinsic` and passes every
+    /// status to `progress`, returns the block including it once finalized
+    ///
+    /// An extrinsic the pool drops, rejects or replaces, or whose block is not finalized in
+    /// time, is an error.
+    pub fn submit_and_track(
+        &self,
+        extrinsic: String,
+        mut progress: impl FnMut(TxProgress),
+    ) -> Result<Hash> {
+        let (sender, receiver) = channel();
+        self.source.submit_and_watch(extrinsic, sender)?;
+        for update in receiver {
+            let update: Value = serde_json::from_str(&update)
+                .map_err(|_| DumplingError::Decode(format!("extrinsic update {}", update)))?;
+            if let Some(error) = update.get("error") {
+                return Err(DumplingError::Extrinsic(match error.get("message") {
+                    Some(Value::String(message)) => message.clone(),
+                    _ => error.to_string(),
+                }));
+            }
+            let status = TxProgress::from_update(&update)
+                .ok_or_else(|| DumplingError::Decode(format!("extrinsic update {}", update)))?;
+            progress(status);
+            match status {
+                TxProgress::Finalized(block) => return Ok(block),
+                TxProgress::FinalityTimeout(_)
+                | TxProgress::Usurped(_)
+                | TxProgress::Dropped
+                | TxProgress::Invalid => {
+                    return Err(DumplingError::Extrinsic(status.to_string()));
+                }
+                _ => {}
+            }
+        }
+        Err(DumplingError::Connection(
+            "author_submitAndWatchExtrinsic subscription ended".to_string(),
+        ))
+    }
+}
@@ -0,0 +1,85 @@
+use dumpling::{
+    alert::{evaluate, AlertKind, Rules, StashStatus, Status},
+    AccountId,
+};
+use sp_runtime::Perbill;
+
+fn healthy() -> StashStatus {
+    StashStatus {
+        stash: AccountId::from([1; 32]),
+        in_session: true,
+        queued: None,
+        commission: Some(Perbill::from_percent(10)),
+        nominators: 20,
+        online: true,
+    }
+}
+
+fn status(block: u32, session_index: u32, stash: StashStatus) -> Status {
+    Status {
+        block,
+        session_index,
+        stashes: vec![stash],
+    }
+}
+
+fn kinds(prev: &Status, next: &Status) -> Vec<AlertKind> {
+    let rules = Rules {
+        min_nominators: Some(10),
+    };
+    evaluate(prev, next, &rules)
+        .into_iter()
+        .map(|a| a.kind)
+        .collect()
+}
+
+#[test]
+fn healthy_stash_raises_nothing() {
+    let prev = status(1, 1, healthy());
+    let next = status(2, 1, healthy());
+    assert!(kinds(&prev, &next).is_empty());
+}
+
+#[test]
+fn every_rule_fires_once() {
+    let prev = status(1, 1, healthy());
+    let next = status(
+        2,
+        1,
+        StashStatus {
+            in_session: false,
+            queued: Some(false),
+            commission: Some(Perbill::from_percent(20)),
+            nominators: 5,
+            ..healthy()
+        },
+    );
+    assert_eq!(
+        kinds(&prev, &next),
+        vec![
+            AlertKind::DroppedFromSession,
+            AlertKind::MissingFromQueued,
+            AlertKind::FewNominators,
+            AlertKind::CommissionChanged,
+        ]
+    );
+    // Conditions that still hold are not raised again
+    assert!(kinds(&next, &status(3, 1, next.stashes[0].clone())).is_empty());
+}
+
+#[test]
+fn offline_when_session_ends_without_heartbeat() {
+    let prev = status(
+        1,
+        1,
+        StashStatus {
+            online: false,
+            ..healthy()
+        },
+    );
+    assert!(kinds(&prev, &status(2, 1, healthy())).is_empty());
+    assert_eq!(
+        kinds(&prev, &status(2, 2, healthy())),
+        vec![AlertKind::ReportedOffline]
+    );
+}
@@ -0,0 +1,676 @@
+use balances::{BalanceLock, Reasons};
+use dumpling::{
+    calls::StakingCall,
+    parse_account,
+    signer::{CryptoType, Signer},
+    storage::Hasher,
+    AccountData, AccountId, AccountInfo, ActiveEraInfo, ApiFilling, Balance, BlakeTwo256,
+    BlockNumber, Chain, DumplingError, ElectionResult, Hash, Header, MemorySource, Ss58Codec,
+    StorageKey, StorageSource,
+};
+use sp_runtime::Perbill;
+use staking::{
+    ElectionCompute, Exposure, IndividualExposure, Nominations, StakingLedger, ValidatorPrefs,
+};
+
+const BLOCK: BlockNumber = 100;
+
+fn account(seed: u8) -> AccountId {
+    AccountId::from([seed; 32])
+}
+
+fn validator() -> AccountId {
+    account(1)
+}
+
+fn waiting() -> AccountId {
+    account(2)
+}
+
+fn controller() -> AccountId {
+    account(3)
+}
+
+fn nominator() -> AccountId {
+    account(4)
+}
+
+fn staking_lock(amount: Balance) -> Vec<BalanceLock<Balance>> {
+    vec![BalanceLock {
+        id: *b"staking ",
+        amount,
+        reasons: Reasons::All,
+    }]
+}
+
+/// A kusama like state at block 100: one elected validator with a bonded controller,
+/// one waiting validator without ledger and a nominator backing both
+fn state() -> MemorySource {
+    let header = Header::<BlockNumber, BlakeTwo256>::new(
+        BLOCK,
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+    );
+    let mut s = MemorySource::new(header);
+    s.insert_value(
+        "Staking",
+        "ActiveEra",
+        &ActiveEraInfo {
+            index: 5,
+            start: Some(1_590_000_000_000),
+        },
+    );
+    s.insert_value("Staking", "CurrentEra", &6u32);
+    s.insert_value("Session", "CurrentIndex", &42u32);
+    s.insert_value("Session", "Validators", &vec![validator()]);
+
+    for (stash, commission) in &[(validator(), 10), (waiting(), 5)] {
+        s.insert_map(
+            "Staking",
+            "Validators",
+            Hasher::Twox64Concat,
+            stash,
+            &ValidatorPrefs {
+                commission: Perbill::from_percent(*commission),
+            },
+        );
+    }
+    s.insert_map(
+        "Staking",
+        "Nominators",
+        Hasher::Twox64Concat,
+        &nominator(),
+        &Nominations {
+            targets: vec![validator(), waiting()],
+            submitted_in: 5,
+            suppressed: false,
+        },
+    );
+    s.insert_map(
+        "Balances",
+        "Locks",
+        Hasher::Blake2_128Concat,
+        &validator(),
+        &staking_lock(1_000),
+    );
+    s.insert_map(
+        "Balances",
+        "Locks",
+        Hasher::Blake2_128Concat,
+        &nominator(),
+        &staking_lock(500),
+    );
+    s.insert_map(
+        "Staking",
+        "Bonded",
+        Hasher::Twox64Concat,
+        &validator(),
+        &controller(),
+    );
+    s.insert_map(
+        "Staking",
+        "Ledger",
+        Hasher::Blake2_128Concat,
+        &controller(),
+        &StakingLedger::<AccountId, Balance> {
+            stash: validator(),
+            total: 1_000,
+            active: 1_000,
+            unlocking: vec![],
+            claimed_rewards: vec![4],
+        },
+    );
+    s
+}
+
+fn queued() -> ElectionResult<AccountId, Balance> {
+    ElectionResult {
+        elected_stashes: vec![validator()],
+        exposures: vec![(
+            validator(),
+            Exposure {
+                total: 1_500,
+                own: 1_000,
+                others: vec![IndividualExposure {
+                    who: nominator(),
+                    value: 500,
+                }],
+            },
+        )],
+        compute: ElectionCompute::OnChain,
+    }
+}
+
+fn filling(state: MemorySource) -> ApiFilling<MemorySource> {
+    ApiFilling::with_source(state, Chain::Kusama)
+}
+
+#[test]
+fn with_source_defaults_to_chain_token() {
+    let d = filling(state());
+    assert_eq!(d.token.symbol, "KSM");
+    assert_eq!(d.token.decimals, 12);
+}
+
+#[test]
+fn rpc_is_unsupported_without_a_node() {
+    let d = filling(state());
+    match d.rpc("system_health", serde_json::json!([])) {
+        Err(DumplingError::Unsupported(_)) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn finalized_head_and_header() {
+    let s = state();
+    let hash = s.hash();
+    let d = filling(s);
+    let (head, header) = d.finalized_head().unwrap();
+    assert_eq!(head, hash);
+    assert_eq!(header.number, BLOCK);
+    assert_eq!(d.header(hash).unwrap().number, BLOCK);
+    assert!(d.header(Hash::repeat_byte(7)).is_err());
+}
+
+#[test]
+fn block_hash_and_resolve_block() {
+    let s = state();
+    let hash = s.hash();
+    let d = filling(s);
+    assert_eq!(d.block_hash(BLOCK).unwrap(), hash);
+    assert!(d.block_hash(BLOCK + 1).is_err());
+    assert_eq!(d.resolve_block("100").unwrap(), hash);
+    let hex = dumpling::storage::to_hex(hash.as_bytes());
+    assert_eq!(d.resolve_block(&hex).unwrap(), hash);
+    assert!(d.resolve_block("latest").is_err());
+}
+
+#[test]
+fn era_and_session_values() {
+    let d = filling(state());
+    let active = d.active_era(None).unwrap();
+    assert_eq!(active.index, 5);
+    assert_eq!(active.start, Some(1_590_000_000_000));
+    assert_eq!(d.planned_era(None).unwrap(), 6);
+    assert_eq!(d.session_index(None).unwrap(), 42);
+    assert_eq!(d.session_validators(None).unwrap(), vec![validator()]);
+}
+
+#[test]
+fn missing_value_is_an_error() {
+    let d = filling(MemorySource::new(state().header));
+    match d.planned_era(None) {
+        Err(DumplingError::MissingStorage(item)) => assert_eq!(item, "Staking::CurrentEra"),
+        other => panic!("unexpected {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn other_blocks_are_not_available() {
+    let d = filling(state());
+    assert!(d.planned_era(Some(Hash::repeat_byte(7))).is_err());
+}
+
+#[test]
+fn queued_validators() {
+    let d = filling(state());
+    assert!(d.queued_validators(None).unwrap().is_none());
+
+    let mut s = state();
+    s.insert_value("Staking", "QueuedElected", &queued());
+    let result = filling(s).queued_validators(None).unwrap().unwrap();
+    assert_eq!(result.elected_stashes, vec![validator()]);
+    assert_eq!(result.exposures[0].1.total, 1_500);
+    assert_eq!(result.exposures[0].1.others[0].who, nominator());
+}
+
+#[test]
+fn waiting_validators() {
+    let d = filling(state());
+    let waitlist = d.waiting_validators(None).unwrap();
+    assert_eq!(waitlist.len(), 2);
+
+    let elected = &waitlist[&validator().to_ss58check()];
+    assert_eq!(elected.staked, 1_000);
+    assert_eq!(elected.prefs.commission, Perbill::from_percent(10));
+    assert_eq!(elected.nominators, vec![nominator().to_ss58check()]);
+    let ledger = elected.ledger.as_ref().unwrap();
+    assert_eq!(ledger.stash, validator());
+    assert_eq!(ledger.claimed_rewards, vec![4]);
+
+    let waiting = &waitlist[&waiting().to_ss58check()];
+    assert_eq!(waiting.staked, 0);
+    assert!(waiting.ledger.is_none());
+    assert_eq!(waiting.nominators, vec![nominator().to_ss58check()]);
+}
+
+#[test]
+fn validators_to_nominators() {
+    let d = filling(state());
+    let v_to_n = d.validators_to_nominators(None).unwrap();
+    assert_eq!(v_to_n.len(), 2);
+    assert_eq!(v_to_n[&validator()], vec![nominator().to_ss58check()]);
+    assert_eq!(v_to_n[&waiting()], vec![nominator().to_ss58check()]);
+}
+
+#[test]
+fn nominators() {
+    let d = filling(state());
+    let nominators = d.nominators(None).unwrap();
+    assert_eq!(nominators.len(), 1);
+    let n = nominators[&nominator().to_ss58check()].as_ref().unwrap();
+    assert_eq!(n.staked, 500);
+    assert_eq!(n.nominations.targets.len(), 2);
+    assert_eq!(n.nominations.submitted_in, 5);
+}
+
+#[test]
+fn keys_paged_and_iter_keys() {
+    let mut d = filling(state());
+    let prefix = dumpling::storage::value_key("Staking", "Validators");
+    let first = d.keys_paged(&prefix, 1, None, None).unwrap();
+    assert_eq!(first.len(), 1);
+    let second = d.keys_paged(&prefix, 1, first.last(), None).unwrap();
+    assert_eq!(second.len(), 1);
+    assert_ne!(first, second);
+    assert!(d
+        .keys_paged(&prefix, 1, second.last(), None)
+        .unwrap()
+        .is_empty());
+
+    d.page_size = 1;
+    let keys = d
+        .iter_keys(prefix, None)
+        .unwrap()
+        .collect::<dumpling::Result<Vec<StorageKey>>>()
+        .unwrap();
+    assert_eq!(keys, vec![first[0].clone(), second[0].clone()]);
+}
+
+#[test]
+fn storage_and_decode_batch() {
+    let mut d = filling(state());
+    d.batch_size = 1;
+    let keys = vec![
+        dumpling::storage::bonded_key(&validator()),
+        dumpling::storage::bonded_key(&waiting()),
+    ];
+    let raw = d.storage_batch(&keys, None).unwrap();
+    assert_eq!(raw.len(), 2);
+    assert!(raw[1].is_none());
+
+    let controllers = d.decode_batch::<AccountId>(&keys, None).unwrap();
+    assert_eq!(controllers, vec![Some(controller()), None]);
+}
+
+#[test]
+fn parse_accounts() {
+    let stash = validator();
+    assert_eq!(parse_account(&stash.to_ss58check()).unwrap(), stash);
+    match parse_account("not an address") {
+        Err(DumplingError::InvalidAddress(a)) => assert_eq!(a, "not an address"),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn snapshot_round_trip() {
+    let mut s = state();
+    s.insert_value("Staking", "QueuedElected", &queued());
+    // Locks of accounts dumpling never reads are left out
+    s.insert_map(
+        "Balances",
+        "Locks",
+        Hasher::Blake2_128Concat,
+        &account(9),
+        &staking_lock(1),
+    );
+    let snapshot = filling(s).snapshot(None).unwrap();
+    assert_eq!(snapshot.header.number, BLOCK);
+    assert!(snapshot
+        .storage(&dumpling::storage::locks_key(&account(9)), None)
+        .unwrap()
+        .is_none());
+
+    let path = std::env::temp_dir().join(format!("dumpling-{}.snapshot", std::process::id()));
+    snapshot.save(&path).unwrap();
+    let loaded = MemorySource::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.entries, snapshot.entries);
+
+    let d = filling(loaded);
+    assert_eq!(d.planned_era(None).unwrap(), 6);
+    assert_eq!(d.waiting_validators(None).unwrap().len(), 2);
+    assert_eq!(d.nominators(None).unwrap().len(), 1);
+    assert!(d.queued_validators(None).unwrap().is_some());
+}
+
+#[test]
+fn load_rejects_other_files() {
+    let path = std::env::temp_dir().join(format!("dumpling-{}.txt", std::process::id()));
+    std::fs::write(&path, b"not a snapshot").unwrap();
+    let loaded = MemorySource::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    match loaded {
+        Err(DumplingError::Decode(_)) => {}
+        other => panic!("unexpected {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn metrics() {
+    let mut s = state();
+    s.insert_value("Staking", "QueuedElected", &queued());
+    let m = filling(s).metrics(None).unwrap();
+    assert_eq!(m.block, BLOCK);
+    assert_eq!((m.active_era, m.planned_era, m.session_index), (5, 6, 42));
+    assert_eq!((m.waiting_validators, m.nominators), (2, 1));
+    assert_eq!(
+        m.exposures,
+        vec![(validator().to_ss58check(), 1_500, 1_000)]
+    );
+    assert_eq!(m.validator_nominators.len(), 2);
+
+    let text = m.render();
+    assert!(text.contains("# TYPE dumpling_active_era gauge\ndumpling_active_era 5\n"));
+    assert!(text.contains(&format!(
+        "dumpling_exposure_own{{stash=\"{}\"}} 1000\n",
+        validator().to_ss58check()
+    )));
+}
+
+#[test]
+fn era_reward_points() {
+    let mut s = state();
+    let mut individual = std::collections::BTreeMap::new();
+    individual.insert(validator(), 20u32);
+    individual.insert(waiting(), 60u32);
+    s.insert_map(
+        "Staking",
+        "ErasRewardPoints",
+        Hasher::Twox64Concat,
+        &5u32,
+        &dumpling::EraRewardPoints {
+            total: 80,
+            individual,
+        },
+    );
+    let d = filling(s);
+    let points = d.era_reward_points(5, None).unwrap();
+    assert_eq!(points.total, 80);
+    assert_eq!(points.ranked(), vec![(&waiting(), 60), (&validator(), 20)]);
+
+    let none = d.era_reward_points(6, None).unwrap();
+    assert_eq!(none.total, 0);
+    assert!(none.individual.is_empty());
+}
+
+/// Rewards of eras 3 and 4 for the elected validator, era 4 is already claimed
+fn with_rewards(mut s: MemorySource) -> MemorySource {
+    let exposure = queued().exposures[0].1.clone();
+    for era in &[3u32, 4] {
+        let mut individual = std::collections::BTreeMap::new();
+        individual.insert(validator(), 20u32);
+        individual.insert(waiting(), 60u32);
+        s.insert_map(
+            "Staking",
+            "ErasRewardPoints",
+            Hasher::Twox64Concat,
+            era,
+            &dumpling::EraRewardPoints {
+                total: 80,
+                individual,
+            },
+        );
+        s.insert_map(
+            "Staking",
+            "ErasValidatorReward",
+            Hasher::Twox64Concat,
+            era,
+            &1_000u128,
+        );
+        s.insert(
+            dumpling::storage::eras_stakers_clipped_key(*era, &validator()),
+            codec::Encode::encode(&exposure),
+        );
+        s.insert(
+            dumpling::storage::eras_validator_prefs_key(*era, &validator()),
+            codec::Encode::encode(&ValidatorPrefs {
+                commission: Perbill::from_percent(10),
+            }),
+        );
+    }
+    s
+}
+
+#[test]
+fn unclaimed_eras() {
+    let d = filling(with_rewards(state()));
+
+    // 250 for the validator, 10% commission and 2/3 of the rest
+    let own = d.unclaimed_eras(&validator(), None).unwrap();
+    assert_eq!(own.len(), 1);
+    assert_eq!((own[0].era, &own[0].validator), (3, &validator()));
+    assert!((174..=176).contains(&own[0].payout));
+
+    // The waiting validator has no ledger, its eras cannot be paid out
+    let nominated = d.unclaimed_eras(&nominator(), None).unwrap();
+    assert_eq!(nominated.len(), 1);
+    assert_eq!(
+        (nominated[0].era, &nominated[0].validator),
+        (3, &validator())
+    );
+    assert!((74..=76).contains(&nominated[0].payout));
+
+    assert!(d.unclaimed_eras(&account(9), None).unwrap().is_empty());
+}
+
+#[test]
+fn unclaimed_eras_of_former_targets() {
+    let mut s = with_rewards(state());
+    // The nominator moved to the waiting validator after era 3
+    s.insert_map(
+        "Staking",
+        "Nominators",
+        Hasher::Twox64Concat,
+        &nominator(),
+        &Nominations {
+            targets: vec![waiting()],
+            submitted_in: 6,
+            suppressed: false,
+        },
+    );
+    let d = filling(s);
+
+    let nominated = d.unclaimed_eras(&nominator(), None).unwrap();
+    assert_eq!(nominated.len(), 1);
+    assert_eq!(
+        (nominated[0].era, &nominated[0].validator),
+        (3, &validator())
+    );
+}
+
+/// Memory source recording every key read
+struct Counting {
+    inner: MemorySource,
+    reads: std::cell::RefCell<Vec<StorageKey>>,
+}
+
+impl StorageSource for Counting {
+    fn storage(
+        &self,
+        key: &StorageKey,
+        block_hash: Option<Hash>,
+    ) -> dumpling::Result<Option<Vec<u8>>> {
+        self.reads.borrow_mut().push(key.clone());
+        self.inner.storage(key, block_hash)
+    }
+
+    fn keys_paged(
+        &self,
+        prefix: &StorageKey,
+        count: u32,
+        start_key: Option<&StorageKey>,
+        block_hash: Option<Hash>,
+    ) -> dumpling::Result<Vec<StorageKey>> {
+        self.inner.keys_paged(prefix, count, start_key, block_hash)
+    }
+
+    fn finalized_head(&self) -> dumpling::Result<Hash> {
+        self.inner.finalized_head()
+    }
+
+    fn header(&self, hash: Hash) -> dumpling::Result<Header<BlockNumber, BlakeTwo256>> {
+        self.inner.header(hash)
+    }
+
+    fn block_hash(&self, number: Option<BlockNumber>) -> dumpling::Result<Hash> {
+        self.inner.block_hash(number)
+    }
+}
+
+#[test]
+fn unclaimed_eras_reads_only_pending_exposures() {
+    let mut s = with_rewards(state());
+    // Nominating the elected validator only, since before era 3
+    s.insert_map(
+        "Staking",
+        "Nominators",
+        Hasher::Twox64Concat,
+        &nominator(),
+        &Nominations {
+            targets: vec![validator()],
+            submitted_in: 1,
+            suppressed: false,
+        },
+    );
+    let d = ApiFilling::with_source(
+        Counting {
+            inner: s,
+            reads: Default::default(),
+        },
+        Chain::Kusama,
+    );
+    let exposure_keys: Vec<StorageKey> = [3, 4]
+        .iter()
+        .flat_map(|era| {
+            vec![
+                dumpling::storage::eras_stakers_clipped_key(*era, &validator()),
+                dumpling::storage::eras_stakers_clipped_key(*era, &waiting()),
+            ]
+        })
+        .collect();
+    let exposures_read = || {
+        let reads = d.source.reads.replace(Vec::new());
+        reads.iter().filter(|k| exposure_keys.contains(k)).count()
+    };
+
+    // Era 4 is claimed and the waiting validator was neither backed nor is it the stash
+    for stash in &[validator(), nominator()] {
+        let unclaimed = d.unclaimed_eras(stash, None).unwrap();
+        assert_eq!(unclaimed.len(), 1);
+        assert_eq!(exposures_read(), 1);
+    }
+}
+
+#[test]
+fn staking_diff_reads_nominators_once_per_block() {
+    let s = state();
+    let hash = s.hash();
+    let d = ApiFilling::with_source(
+        Counting {
+            inner: s,
+            reads: Default::default(),
+        },
+        Chain::Kusama,
+    );
+
+    let diff = d.staking_diff(hash, hash).unwrap();
+    assert!(diff.validators_entered.is_empty() && diff.nominators_added.is_empty());
+    let key = dumpling::storage::nominators_key(&nominator());
+    let reads = d.source.reads.borrow();
+    assert_eq!(reads.iter().filter(|k| **k == key).count(), 2);
+}
+
+#[test]
+fn ledger_for_stash_and_controller() {
+    let d = filling(state());
+    let bond_extra = StakingCall::BondExtra { value: 1 };
+    let unbond = StakingCall::Unbond { value: 1 };
+
+    let by_stash = d.ledger_for(&bond_extra, &validator(), None).unwrap();
+    assert_eq!(by_stash.map(|l| l.total), Some(1_000));
+    let by_controller = d.ledger_for(&unbond, &controller(), None).unwrap();
+    assert_eq!(by_controller.map(|l| l.stash), Some(validator()));
+
+    assert!(d.ledger_for(&unbond, &validator(), None).unwrap().is_none());
+    assert!(d
+        .ledger_for(&bond_extra, &waiting(), None)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn signing_needs_a_node() {
+    let d = filling(state());
+    let signer = Signer::from_uri("//Alice", CryptoType::Ed25519).unwrap();
+    match d.sign_staking_call(&signer, &StakingCall::WithdrawUnbonded) {
+        Err(DumplingError::Unsupported(_)) => {}
+        _ => panic!("signed without a node"),
+    }
+}
+
+#[test]
+fn check_nominations() {
+    let mut s = state();
+    let mut election = queued();
+    election.exposures[0].1.others = (0..65)
+        .map(|i| IndividualExposure {
+            who: account(100 + i),
+            value: 1,
+        })
+        .collect();
+    s.insert_value("Staking", "QueuedElected", &election);
+    let d = filling(s);
+
+    let check = d
+        .check_nominations(&[validator(), waiting()], None)
+        .unwrap();
+    assert!(check.passed());
+    assert_eq!(check.oversubscribed, vec![(validator(), 65)]);
+
+    let check = d.check_nominations(&[nominator()], None).unwrap();
+    assert!(!check.passed());
+    assert_eq!(check.unknown, vec![nominator()]);
+
+    let check = d.check_nominations(&vec![waiting(); 17], None).unwrap();
+    assert!(check.too_many);
+}
+
+#[test]
+fn account_info() {
+    let mut s = state();
+    let info = AccountInfo {
+        nonce: 3,
+        refcount: 1,
+        data: AccountData {
+            free: 500,
+            reserved: 0,
+            misc_frozen: 100,
+            fee_frozen: 100,
+        },
+    };
+    s.insert_map(
+        "System",
+        "Account",
+        Hasher::Blake2_128Concat,
+        &validator(),
+        &info,
+    );
+    let d = filling(s);
+    assert_eq!(d.account_info(&validator(), None).unwrap(), Some(info));
+    assert_eq!(d.account_info(&nominator(), None).unwrap(), None);
+}
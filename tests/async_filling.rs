@@ -0,0 +1,212 @@
+#![cfg(feature = "async")]
+
+use balances::{BalanceLock, Reasons};
+use dumpling::{
+    storage::{self, Hasher},
+    AccountId, ActiveEraInfo, ApiFilling, AsyncApiFilling, Balance, BlakeTwo256, BlockNumber,
+    Chain, EraRewardPoints, Header, MemorySource, Ss58Codec, StorageKey,
+};
+use futures::executor::block_on;
+use sp_runtime::Perbill;
+use staking::{Exposure, IndividualExposure, Nominations, StakingLedger, ValidatorPrefs};
+use std::collections::BTreeMap;
+
+fn account(seed: u8) -> AccountId {
+    AccountId::from([seed; 32])
+}
+
+fn validator() -> AccountId {
+    account(1)
+}
+
+fn waiting() -> AccountId {
+    account(2)
+}
+
+fn controller() -> AccountId {
+    account(3)
+}
+
+fn nominator() -> AccountId {
+    account(4)
+}
+
+/// Block 100 with an elected validator, a waiting one and a nominator backing both,
+/// era 3 is still to be paid out
+fn state() -> MemorySource {
+    let header = Header::<BlockNumber, BlakeTwo256>::new(
+        100,
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+    );
+    let mut s = MemorySource::new(header);
+    s.insert_value(
+        "Staking",
+        "ActiveEra",
+        &ActiveEraInfo {
+            index: 5,
+            start: None,
+        },
+    );
+    s.insert_value("Staking", "CurrentEra", &6u32);
+    s.insert_value("Session", "CurrentIndex", &42u32);
+    for stash in &[validator(), waiting()] {
+        s.insert_map(
+            "Staking",
+            "Validators",
+            Hasher::Twox64Concat,
+            stash,
+            &ValidatorPrefs {
+                commission: Perbill::from_percent(10),
+            },
+        );
+    }
+    s.insert_map(
+        "Staking",
+        "Nominators",
+        Hasher::Twox64Concat,
+        &nominator(),
+        &Nominations {
+            targets: vec![validator(), waiting()],
+            submitted_in: 5,
+            suppressed: false,
+        },
+    );
+    s.insert_map(
+        "Balances",
+        "Locks",
+        Hasher::Blake2_128Concat,
+        &nominator(),
+        &vec![BalanceLock::<Balance> {
+            id: *b"staking ",
+            amount: 500,
+            reasons: Reasons::All,
+        }],
+    );
+    s.insert_map(
+        "Staking",
+        "Bonded",
+        Hasher::Twox64Concat,
+        &validator(),
+        &controller(),
+    );
+    s.insert_map(
+        "Staking",
+        "Ledger",
+        Hasher::Blake2_128Concat,
+        &controller(),
+        &StakingLedger::<AccountId, Balance> {
+            stash: validator(),
+            total: 1_000,
+            active: 1_000,
+            unlocking: vec![],
+            claimed_rewards: vec![],
+        },
+    );
+
+    let mut individual = BTreeMap::new();
+    individual.insert(validator(), 20u32);
+    s.insert_map(
+        "Staking",
+        "ErasRewardPoints",
+        Hasher::Twox64Concat,
+        &3u32,
+        &EraRewardPoints {
+            total: 20,
+            individual,
+        },
+    );
+    s.insert_map(
+        "Staking",
+        "ErasValidatorReward",
+        Hasher::Twox64Concat,
+        &3u32,
+        &1_000u128,
+    );
+    s.insert(
+        storage::eras_stakers_clipped_key(3, &validator()),
+        codec::Encode::encode(&Exposure::<AccountId, Balance> {
+            total: 1_500,
+            own: 1_000,
+            others: vec![IndividualExposure {
+                who: nominator(),
+                value: 500,
+            }],
+        }),
+    );
+    s
+}
+
+fn filling(state: MemorySource) -> AsyncApiFilling<MemorySource> {
+    block_on(AsyncApiFilling::with_source(state, Chain::Kusama))
+}
+
+#[test]
+fn queries_on_memory_source() {
+    let d = filling(state());
+    assert_eq!(block_on(d.planned_era(None)).unwrap(), 6);
+    assert_eq!(block_on(d.active_era(None)).unwrap().index, 5);
+
+    let waitlist = block_on(d.waiting_validators(None)).unwrap();
+    assert_eq!(waitlist.len(), 2);
+    let elected = &waitlist[&validator().to_ss58check()];
+    assert_eq!(elected.nominators, vec![nominator().to_ss58check()]);
+    assert_eq!(elected.ledger.as_ref().unwrap().stash, validator());
+
+    let nominators = block_on(d.nominators(None)).unwrap();
+    let n = nominators[&nominator().to_ss58check()].as_ref().unwrap();
+    assert_eq!(n.staked, 500);
+}
+
+#[test]
+fn pin_keys_and_batches() {
+    let mut d = filling(state());
+    d.page_size = 1;
+    d.batch_size = 1;
+
+    let prefix = storage::value_key("Staking", "Validators");
+    let keys = block_on(d.keys(prefix, None)).unwrap();
+    assert_eq!(keys.len(), 2);
+
+    // Chunks are read concurrently, values still come back in order
+    let bonded: Vec<StorageKey> = vec![
+        storage::bonded_key(&waiting()),
+        storage::bonded_key(&validator()),
+    ];
+    let raw = block_on(d.storage_batch(&bonded, None)).unwrap();
+    assert!(raw[0].is_none() && raw[1].is_some());
+    let controllers = block_on(d.decode_batch::<AccountId>(&bonded, None)).unwrap();
+    assert_eq!(controllers, vec![None, Some(controller())]);
+
+    // Block numbers resolve to the hashes of the source
+    let (head, _) = block_on(d.finalized_head()).unwrap();
+    let hash = block_on(d.resolve_block("100")).unwrap();
+    assert_eq!(hash, head);
+    assert!(block_on(d.resolve_block("101")).is_err());
+}
+
+#[test]
+fn in_step_with_api_filling() {
+    let d = filling(state());
+    let sync = ApiFilling::with_source(state(), Chain::Kusama);
+
+    for stash in &[validator(), nominator()] {
+        let unclaimed = block_on(d.unclaimed_eras(stash, None)).unwrap();
+        assert_eq!(unclaimed.len(), 1);
+        assert_eq!(unclaimed, sync.unclaimed_eras(stash, None).unwrap());
+    }
+
+    let metrics = block_on(d.metrics(None)).unwrap();
+    let expected = sync.metrics(None).unwrap();
+    assert_eq!(
+        (metrics.waiting_validators, metrics.nominators),
+        (expected.waiting_validators, expected.nominators)
+    );
+    assert_eq!(metrics.validator_nominators, expected.validator_nominators);
+
+    let (head, _) = block_on(d.finalized_head()).unwrap();
+    let diff = block_on(d.staking_diff(head, head)).unwrap();
+    assert!(diff.validators_entered.is_empty() && diff.stake_changes.is_empty());
+}
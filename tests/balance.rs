@@ -0,0 +1,79 @@
+use dumpling::balance::{BalanceFormatter, TokenInfo, MAX_DECIMALS};
+use serde_json::json;
+
+fn dot(raw: bool) -> BalanceFormatter {
+    BalanceFormatter::new(
+        TokenInfo {
+            decimals: 10,
+            symbol: "DOT".to_string(),
+        },
+        raw,
+    )
+}
+
+#[test]
+fn format_amounts() {
+    assert_eq!(dot(false).format(15_000_000_000), "1.5 DOT");
+    assert_eq!(dot(false).format(20_000_000_000), "2 DOT");
+    assert_eq!(dot(true).format(15_000_000_000), "15000000000");
+}
+
+#[test]
+fn parse_amounts() {
+    assert_eq!(dot(false).parse("1.5"), Some(15_000_000_000));
+    assert_eq!(dot(false).parse("2"), Some(20_000_000_000));
+    assert_eq!(dot(false).parse(".0000000001"), Some(1));
+    assert_eq!(dot(true).parse("15000000000"), Some(15_000_000_000));
+
+    assert_eq!(dot(false).parse("0.00000000001"), None);
+    assert_eq!(dot(false).parse("1.5 DOT"), None);
+    assert_eq!(dot(true).parse("1.5"), None);
+}
+
+#[test]
+fn token_from_properties() {
+    let properties = json!({ "ss58Format": 0, "tokenDecimals": 10, "tokenSymbol": "DOT" });
+    assert_eq!(
+        TokenInfo::from_properties(&properties),
+        Some(TokenInfo {
+            decimals: 10,
+            symbol: "DOT".to_string(),
+        })
+    );
+
+    let multi_token = json!({ "tokenDecimals": [12, 18], "tokenSymbol": ["KAR", "KUSD"] });
+    assert_eq!(
+        TokenInfo::from_properties(&multi_token).map(|t| (t.decimals, t.symbol)),
+        Some((12, "KAR".to_string()))
+    );
+
+    assert_eq!(
+        TokenInfo::from_properties(&json!({ "tokenSymbol": "DOT" })),
+        None
+    );
+    assert_eq!(TokenInfo::from_properties(&json!(null)), None);
+}
+
+#[test]
+fn untrusted_decimals() {
+    for decimals in &[39, 256, 1 << 40] {
+        let properties = json!({ "tokenDecimals": decimals, "tokenSymbol": "BAD" });
+        assert_eq!(TokenInfo::from_properties(&properties), None);
+    }
+    let properties = json!({ "tokenDecimals": MAX_DECIMALS, "tokenSymbol": "MAX" });
+    let token = TokenInfo::from_properties(&properties).unwrap();
+    assert_eq!(
+        BalanceFormatter::new(token, false).format(u128::max_value()),
+        "3.40282366920938463463374607431768211455 MAX"
+    );
+
+    let too_precise = TokenInfo {
+        decimals: 40,
+        symbol: "BAD".to_string(),
+    };
+    assert_eq!(
+        BalanceFormatter::new(too_precise.clone(), false).format(5),
+        "5"
+    );
+    assert_eq!(BalanceFormatter::new(too_precise, false).parse("1"), None);
+}
@@ -0,0 +1,109 @@
+use dumpling::{
+    calls::{StakingCall, BONDING_DURATION},
+    AccountId, Balance, Perbill, RewardDestination, StakingLedger, UnlockChunk,
+};
+
+fn stash() -> AccountId {
+    AccountId::from([1; 32])
+}
+
+fn ledger(active: Balance, unlocking: &[(Balance, u32)]) -> StakingLedger<AccountId, Balance> {
+    let unlocking: Vec<UnlockChunk<Balance>> = unlocking
+        .iter()
+        .map(|&(value, era)| UnlockChunk { value, era })
+        .collect();
+    StakingLedger {
+        stash: stash(),
+        total: active + unlocking.iter().map(|c| c.value).sum::<Balance>(),
+        active,
+        unlocking,
+        claimed_rewards: vec![],
+    }
+}
+
+fn predict(
+    call: StakingCall,
+    before: Option<&StakingLedger<AccountId, Balance>>,
+) -> Option<StakingLedger<AccountId, Balance>> {
+    call.predict(&stash(), before, 10)
+}
+
+#[test]
+fn bond_creates_a_ledger() {
+    let bond = || StakingCall::Bond {
+        controller: AccountId::from([2; 32]),
+        value: 100,
+        payee: RewardDestination::Staked,
+    };
+    assert_eq!(predict(bond(), None), Some(ledger(100, &[])));
+    // Bonding twice fails, the ledger stays
+    assert_eq!(predict(bond(), Some(&ledger(5, &[]))), Some(ledger(5, &[])));
+}
+
+#[test]
+fn calls_need_a_ledger() {
+    assert_eq!(predict(StakingCall::BondExtra { value: 1 }, None), None);
+    assert_eq!(predict(StakingCall::WithdrawUnbonded, None), None);
+}
+
+#[test]
+fn bond_extra_and_unbond() {
+    let before = ledger(100, &[]);
+    assert_eq!(
+        predict(StakingCall::BondExtra { value: 50 }, Some(&before)),
+        Some(ledger(150, &[]))
+    );
+    assert_eq!(
+        predict(StakingCall::Unbond { value: 30 }, Some(&before)),
+        Some(ledger(70, &[(30, 10 + BONDING_DURATION)]))
+    );
+    // Unbonding more than is active unbonds all of it
+    assert_eq!(
+        predict(StakingCall::Unbond { value: 500 }, Some(&before)),
+        Some(ledger(0, &[(100, 10 + BONDING_DURATION)]))
+    );
+}
+
+#[test]
+fn rebond_takes_latest_chunks_first() {
+    let before = ledger(10, &[(20, 5), (30, 8)]);
+    assert_eq!(
+        predict(StakingCall::Rebond { value: 40 }, Some(&before)),
+        Some(ledger(50, &[(10, 5)]))
+    );
+    assert_eq!(
+        predict(StakingCall::Rebond { value: 100 }, Some(&before)),
+        Some(ledger(60, &[]))
+    );
+}
+
+#[test]
+fn withdraw_unbonded_chunks() {
+    let before = ledger(10, &[(20, 5), (30, 12)]);
+    assert_eq!(
+        predict(StakingCall::WithdrawUnbonded, Some(&before)),
+        Some(ledger(10, &[(30, 12)]))
+    );
+    // Nothing left, the stash is no longer bonded
+    assert_eq!(
+        predict(StakingCall::WithdrawUnbonded, Some(&ledger(0, &[(20, 5)]))),
+        None
+    );
+}
+
+#[test]
+fn role_calls_keep_the_ledger() {
+    let before = ledger(100, &[(20, 5)]);
+    for call in vec![
+        StakingCall::Nominate {
+            targets: vec![AccountId::from([2; 32])],
+        },
+        StakingCall::Validate {
+            commission: Perbill::from_percent(5),
+        },
+        StakingCall::Chill,
+    ] {
+        assert!(!call.changes_ledger());
+        assert_eq!(predict(call, Some(&before)), Some(before.clone()));
+    }
+}
@@ -0,0 +1,69 @@
+use dumpling::{
+    keystore::Keystore,
+    signer::{CryptoType, Signer},
+    BlakeTwo256, BlockNumber, Header, MemorySource,
+};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[test]
+fn import_with_password_env() {
+    let home = env::temp_dir().join(format!("dumpling-cli-import-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&home);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dumpling"))
+        .args(&["keys", "import", "alice", "--uri-stdin"])
+        .env("XDG_CONFIG_HOME", &home)
+        .env("XDG_DATA_HOME", &home)
+        .env("DUMPLING_PASSWORD", "pass")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"//Alice\n").unwrap();
+    assert!(child.wait().unwrap().success());
+
+    // The secret uri is read from stdin, the password only encrypts it
+    let keystore = Keystore::new(home.join("dumpling").join("keystore"));
+    let alice = Signer::from_uri("//Alice", CryptoType::Sr25519).unwrap();
+    let unlocked = keystore.unlock("alice", "pass").unwrap();
+    assert_eq!(unlocked.account(), alice.account());
+
+    fs::remove_dir_all(&home).unwrap();
+}
+
+#[test]
+fn no_queued_validators_in_every_format() {
+    let home = env::temp_dir().join(format!("dumpling-cli-queued-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&home);
+    fs::create_dir_all(&home).unwrap();
+    let header = Header::<BlockNumber, BlakeTwo256>::new(
+        100,
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+    );
+    let snapshot = home.join("empty.snapshot");
+    MemorySource::new(header).save(&snapshot).unwrap();
+
+    let queued = |format: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_dumpling"))
+            .arg("--snapshot")
+            .arg(&snapshot)
+            .args(&["--chain", "kusama", "--output", format])
+            .args(&["validators", "--queued"])
+            .env("XDG_CONFIG_HOME", &home)
+            .env("XDG_DATA_HOME", &home)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(queued("json").trim(), "null");
+    assert_eq!(queued("csv").trim(), "stash,total,own,others");
+    assert_eq!(queued("table").trim(), "None");
+
+    fs::remove_dir_all(&home).unwrap();
+}
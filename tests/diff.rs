@@ -0,0 +1,88 @@
+use dumpling::{diff::diff, AccountId, Nominator, Ss58Codec, WaitingValidator};
+use sp_runtime::Perbill;
+use staking::{Nominations, ValidatorPrefs};
+use std::collections::HashMap;
+
+fn account(seed: u8) -> AccountId {
+    AccountId::from([seed; 32])
+}
+
+fn validator(staked: u128, commission: u32) -> WaitingValidator {
+    WaitingValidator {
+        staked,
+        prefs: ValidatorPrefs {
+            commission: Perbill::from_percent(commission),
+        },
+        nominators: vec![],
+        ledger: None,
+    }
+}
+
+fn nominator(staked: u128, targets: Vec<AccountId>) -> Option<Nominator> {
+    Some(Nominator {
+        nominations: Nominations {
+            targets,
+            submitted_in: 1,
+            suppressed: false,
+        },
+        staked,
+    })
+}
+
+#[test]
+fn reports_every_kind_of_change() {
+    let (v1, v2, v3) = (account(1), account(2), account(3));
+    let (n1, n2, n3) = (account(4), account(5), account(6));
+
+    let mut validators_before = HashMap::new();
+    validators_before.insert(v1.to_ss58check(), validator(100, 10));
+    validators_before.insert(v2.to_ss58check(), validator(100, 10));
+    let mut validators_after = HashMap::new();
+    validators_after.insert(v1.to_ss58check(), validator(150, 5));
+    validators_after.insert(v3.to_ss58check(), validator(100, 10));
+
+    let mut nominators_before = HashMap::new();
+    nominators_before.insert(
+        n1.to_ss58check(),
+        nominator(10, vec![v1.clone(), v2.clone()]),
+    );
+    nominators_before.insert(n2.to_ss58check(), nominator(10, vec![v1.clone()]));
+    let mut nominators_after = HashMap::new();
+    nominators_after.insert(
+        n1.to_ss58check(),
+        nominator(20, vec![v1.clone(), v3.clone()]),
+    );
+    nominators_after.insert(n3.to_ss58check(), nominator(10, vec![v3.clone()]));
+
+    let d = diff(
+        &validators_before,
+        &nominators_before,
+        &validators_after,
+        &nominators_after,
+    );
+    assert_eq!(d.validators_entered, vec![v3.to_ss58check()]);
+    assert_eq!(d.validators_left, vec![v2.to_ss58check()]);
+    assert_eq!(d.nominators_added, vec![n3.to_ss58check()]);
+    assert_eq!(d.nominators_removed, vec![n2.to_ss58check()]);
+
+    assert_eq!(d.targets_changed.len(), 1);
+    assert_eq!(d.targets_changed[0].stash, n1.to_ss58check());
+    assert_eq!(d.targets_changed[0].added, vec![v3]);
+    assert_eq!(d.targets_changed[0].removed, vec![v2]);
+
+    let stakes: Vec<(u128, u128)> = d.stake_changes.iter().map(|s| (s.from, s.to)).collect();
+    assert_eq!(stakes, vec![(100, 150), (10, 20)]);
+
+    assert_eq!(d.commission_changes.len(), 1);
+    assert_eq!(d.commission_changes[0].from, Perbill::from_percent(10));
+    assert_eq!(d.commission_changes[0].to, Perbill::from_percent(5));
+}
+
+#[test]
+fn same_state_has_no_changes() {
+    let mut validators = HashMap::new();
+    validators.insert(account(1).to_ss58check(), validator(100, 10));
+    let mut nominators = HashMap::new();
+    nominators.insert(account(2).to_ss58check(), nominator(10, vec![account(1)]));
+    assert!(diff(&validators, &nominators, &validators, &nominators).is_empty());
+}
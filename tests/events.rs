@@ -0,0 +1,52 @@
+use codec::Encode;
+use dumpling::{
+    events::{TxEvent, TxOutcome},
+    AccountId, Balance,
+};
+use substrate_api_client::events::SystemEvent;
+
+fn stash() -> AccountId {
+    AccountId::from([1; 32])
+}
+
+#[test]
+fn staking_events() {
+    let data = (stash(), 500 as Balance).encode();
+    assert_eq!(
+        TxEvent::from_raw("Staking", "Bonded", &data).unwrap(),
+        TxEvent::Bonded(stash(), 500)
+    );
+    assert_eq!(
+        TxEvent::from_raw("Staking", "Reward", &data).unwrap(),
+        TxEvent::Reward(stash(), 500)
+    );
+    assert!(TxEvent::from_raw("Staking", "Reward", &data[..32]).is_err());
+    assert_eq!(
+        TxEvent::from_raw("Balances", "Transfer", &[]).unwrap(),
+        TxEvent::Other("Balances".to_string(), "Transfer".to_string())
+    );
+}
+
+#[test]
+fn system_events_by_name() {
+    assert_eq!(
+        TxEvent::from_system(&SystemEvent::NewAccount(stash())),
+        TxEvent::Other("System".to_string(), "NewAccount".to_string())
+    );
+    assert_eq!(
+        TxEvent::from_system(&SystemEvent::CodeUpdated),
+        TxEvent::Other("System".to_string(), "CodeUpdated".to_string())
+    );
+}
+
+#[test]
+fn outcome_error() {
+    let mut outcome = TxOutcome {
+        index: 2,
+        events: vec![TxEvent::Bonded(stash(), 500), TxEvent::Success],
+    };
+    assert_eq!(outcome.error(), None);
+
+    outcome.events = vec![TxEvent::Failed("Staking::AlreadyBonded".to_string())];
+    assert_eq!(outcome.error(), Some("Staking::AlreadyBonded"));
+}
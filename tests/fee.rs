@@ -0,0 +1,37 @@
+use dumpling::fee::FeeInfo;
+use serde_json::json;
+
+#[test]
+fn fee_from_response() {
+    let info = json!({ "weight": 195000000, "class": "Normal", "partialFee": 15400000000u64 });
+    assert_eq!(
+        FeeInfo::from_response(&info),
+        Some(FeeInfo {
+            weight: 195_000_000,
+            class: "Normal".to_string(),
+            partial_fee: 15_400_000_000,
+        })
+    );
+}
+
+#[test]
+fn large_fees_are_strings() {
+    let info = json!({ "weight": 1, "class": "Operational", "partialFee": "340282366920938463463374607431768211455" });
+    assert_eq!(
+        FeeInfo::from_response(&info).unwrap().partial_fee,
+        u128::max_value()
+    );
+}
+
+#[test]
+fn incomplete_responses() {
+    assert_eq!(
+        FeeInfo::from_response(&json!({ "weight": 1, "class": "Normal" })),
+        None
+    );
+    assert_eq!(
+        FeeInfo::from_response(&json!({ "weight": 1, "partialFee": "0x10" })),
+        None
+    );
+    assert_eq!(FeeInfo::from_response(&json!(null)), None);
+}
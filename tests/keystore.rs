@@ -0,0 +1,107 @@
+use dumpling::{
+    keystore::{self, Keystore},
+    signer::{CryptoType, Signer},
+    DumplingError,
+};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn dir(test: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("dumpling-keystore-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn alice(crypto: CryptoType) -> Signer {
+    Signer::from_uri("//Alice", crypto).unwrap()
+}
+
+#[test]
+fn encrypt_and_decrypt() {
+    for crypto in &[CryptoType::Sr25519, CryptoType::Ed25519] {
+        let signer = alice(*crypto);
+        let json = keystore::encrypt(&signer, "alice", "secret").unwrap();
+        assert_eq!(json.encoding.content[1], crypto.to_string());
+
+        let decrypted = keystore::decrypt(&json, "secret").unwrap();
+        assert_eq!(decrypted.crypto(), *crypto);
+        assert_eq!(decrypted.account(), signer.account());
+
+        match keystore::decrypt(&json, "wrong") {
+            Err(DumplingError::InvalidKey(_)) => {}
+            _ => panic!("decrypted with a wrong password"),
+        }
+    }
+}
+
+#[test]
+fn sr25519_and_ed25519_accounts_differ() {
+    assert_ne!(
+        alice(CryptoType::Sr25519).account(),
+        alice(CryptoType::Ed25519).account()
+    );
+}
+
+#[test]
+fn add_list_and_unlock() {
+    let keystore = Keystore::new(dir("add"));
+    assert!(keystore.list().unwrap().is_empty());
+
+    let stash = alice(CryptoType::Sr25519);
+    let controller = Signer::from_uri("//Bob", CryptoType::Ed25519).unwrap();
+    keystore.add(&stash, "stash", "one").unwrap();
+    keystore.add(&controller, "controller", "two").unwrap();
+    assert!(keystore.add(&stash, "again", "one").is_err());
+
+    let entries = keystore.list().unwrap();
+    let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, vec!["controller", "stash"]);
+    assert_eq!(entries[0].crypto, CryptoType::Ed25519);
+
+    let unlocked = keystore.unlock("stash", "one").unwrap();
+    assert_eq!(unlocked.account(), stash.account());
+    let by_account = keystore
+        .unlock(&controller.account().to_string(), "two")
+        .unwrap();
+    assert_eq!(by_account.account(), controller.account());
+    assert!(keystore.unlock("stash", "two").is_err());
+    assert!(keystore.unlock("nobody", "one").is_err());
+
+    fs::remove_dir_all(&keystore.dir).unwrap();
+}
+
+#[test]
+fn import_json_export() {
+    let keystore = Keystore::new(dir("import"));
+    let signer = alice(CryptoType::Sr25519);
+    let export =
+        serde_json::to_string(&keystore::encrypt(&signer, "alice", "pass").unwrap()).unwrap();
+
+    assert!(keystore.import_json(&export, "mine", "wrong").is_err());
+    let entry = keystore.import_json(&export, "mine", "pass").unwrap();
+    assert_eq!(
+        (entry.name.as_str(), &entry.account),
+        ("mine", &signer.account())
+    );
+    assert!(keystore.import_json("{}", "other", "pass").is_err());
+
+    fs::remove_dir_all(&keystore.dir).unwrap();
+}
+
+#[test]
+fn oversized_scrypt_params() {
+    let json = keystore::encrypt(&alice(CryptoType::Sr25519), "alice", "secret").unwrap();
+    let encoded = base64::decode(&json.encoded).unwrap();
+    // N, p and r follow the 32 byte salt
+    for (at, value) in &[(32, 1u32 << 31), (40, 1 << 20), (36, 16)] {
+        let mut crafted = encoded.clone();
+        crafted[*at..at + 4].copy_from_slice(&value.to_le_bytes());
+        let mut key = json.clone();
+        key.encoded = base64::encode(&crafted);
+        match keystore::decrypt(&key, "secret") {
+            Err(DumplingError::InvalidKey(_)) => {}
+            _ => panic!("decrypted with scrypt parameter {} at {}", value, at),
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use codec::{Decode, Encode};
+use dumpling::{
+    ed25519,
+    signer::{CryptoType, Signer},
+    storage,
+    tx::{self, TxProgress, UnsignedTx},
+    DumplingError, Hash, Pair, Ss58Codec,
+};
+use serde_json::json;
+
+fn alice() -> Signer {
+    Signer::from_uri("//Alice", CryptoType::Ed25519).unwrap()
+}
+
+fn unsigned(call: &[u8]) -> UnsignedTx {
+    UnsignedTx {
+        signer: alice().account().to_ss58check(),
+        call: storage::to_hex(call),
+        nonce: 7,
+        tip: 0,
+        // Immortal
+        era: "0x00".to_string(),
+        era_hash: Hash::repeat_byte(1),
+        genesis_hash: Hash::repeat_byte(1),
+        spec_version: 2012,
+        transaction_version: Some(3),
+    }
+}
+
+#[test]
+fn signing_payload() {
+    let payload = unsigned(&[6, 2]).signing_payload().unwrap();
+    let mut expected = vec![6, 2, 0, 7 << 2, 0];
+    expected.extend(&2012u32.to_le_bytes());
+    expected.extend(&3u32.to_le_bytes());
+    expected.extend(&[1; 64][..]);
+    assert_eq!(payload, expected);
+
+    let mut without_version = unsigned(&[6, 2]);
+    without_version.transaction_version = None;
+    assert_eq!(
+        without_version.signing_payload().unwrap().len(),
+        expected.len() - 4
+    );
+}
+
+#[test]
+fn long_payloads_are_hashed() {
+    let payload = unsigned(&[0; 300]).signing_payload().unwrap();
+    assert_eq!(payload.len(), 32);
+}
+
+#[test]
+fn sign_offline() {
+    let call = [6, 2];
+    let tx = unsigned(&call);
+    let extrinsic = tx.sign(&alice()).unwrap();
+
+    let body = Vec::<u8>::decode(&mut &storage::from_hex(&extrinsic).unwrap()[..]).unwrap();
+    assert_eq!(body[0], 0b1000_0100);
+    assert!(body.ends_with(&[0, 7 << 2, 0, 6, 2]));
+    // MultiSignature::Ed25519 right before the era, nonce, tip and call
+    let end = body.len() - 5;
+    let signature = &body[end - 65..end];
+    assert_eq!(signature[0], 0);
+    let public = match alice() {
+        Signer::Ed25519(pair) => pair.public(),
+        _ => unreachable!(),
+    };
+    assert!(ed25519::Pair::verify(
+        &ed25519::Signature::from_slice(&signature[1..]),
+        tx.signing_payload().unwrap(),
+        &public
+    ));
+
+    let hash = tx::extrinsic_hash(&extrinsic).unwrap();
+    assert_eq!(
+        hash,
+        Hash::from(sp_core::hashing::blake2_256(&body.encode()))
+    );
+}
+
+#[test]
+fn sign_checks_the_signer() {
+    let bob = Signer::from_uri("//Bob", CryptoType::Ed25519).unwrap();
+    match unsigned(&[6, 2]).sign(&bob) {
+        Err(DumplingError::InvalidKey(_)) => {}
+        _ => panic!("signed by another account"),
+    }
+}
+
+#[test]
+fn unsigned_tx_json() {
+    let tx = unsigned(&[6, 2]);
+    let json = serde_json::to_string(&tx).unwrap();
+    assert!(json.contains("\"genesisHash\""));
+    assert_eq!(serde_json::from_str::<UnsignedTx>(&json).unwrap(), tx);
+}
+
+#[test]
+fn progress_from_updates() {
+    let block = Hash::repeat_byte(2);
+    let hex = storage::to_hex(block.as_bytes());
+    let updates = vec![
+        (json!("ready"), TxProgress::Ready),
+        (
+            json!({ "broadcast": ["peer1", "peer2"] }),
+            TxProgress::Broadcast(2),
+        ),
+        (json!({ "inBlock": hex }), TxProgress::InBlock(block)),
+        (json!({ "retracted": hex }), TxProgress::Retracted(block)),
+        (json!({ "finalized": hex }), TxProgress::Finalized(block)),
+        (json!("invalid"), TxProgress::Invalid),
+    ];
+    for (update, progress) in updates {
+        assert_eq!(TxProgress::from_update(&update), Some(progress));
+    }
+    assert_eq!(TxProgress::from_update(&json!("unknown")), None);
+    assert_eq!(TxProgress::from_update(&json!({ "inBlock": "0x12" })), None);
+    assert_eq!(
+        TxProgress::Finalized(block).to_string(),
+        format!("Finalized in block {}", hex)
+    );
+}